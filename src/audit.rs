@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use colored::{ColoredString, Colorize};
+
+use crate::config::{create_config_from_path, load_global_config, Dependency};
+use crate::logging::*;
+use crate::pdm::{get_dep_filename, get_dep_list};
+use crate::registry::{is_git_location, sync_git_repo};
+
+/// One advisory file in the database, any `.toml` file below its root
+#[derive(serde::Deserialize)]
+pub struct AdvisoryFile {
+    pub advisory: Advisory,
+    #[serde(default)]
+    pub versions: Versions,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    /// Name of the affected package
+    pub package: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub date: Option<String>,
+}
+
+/// Version requirements, like `">=0.1.0, <0.2.3"`
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub struct Versions {
+    /// Ranges with the vulnerability
+    #[serde(default)]
+    pub affected: Vec<String>,
+    /// Ranges with the fix, which are never reported even if they fall in an affected range
+    #[serde(default)]
+    pub patched: Vec<String>,
+}
+
+fn parse_ranges(ranges : &[String], path : &Path) -> Result<Vec<semver::VersionReq>, ColoredString>
+{
+    ranges.iter()
+        .map(|range| semver::VersionReq::parse(range)
+            .map_err(|e| format!("{} Invalid version range \"{range}\" in \"{}\": {e}", "Audit error:".red(), path.to_string_lossy()).into()))
+        .collect()
+}
+
+impl AdvisoryFile {
+    /// Whether a version of the package has the vulnerability
+    fn affects(&self, version : &semver::Version, path : &Path) -> Result<bool, ColoredString>
+    {
+        let affected = parse_ranges(&self.versions.affected, path)?;
+        let patched = parse_ranges(&self.versions.patched, path)?;
+        Ok(affected.iter().any(|r| r.matches(version)) && !patched.iter().any(|r| r.matches(version)))
+    }
+}
+
+/// Advisory database location: `--db`, then `[audit]` in Config.toml, then in the global config
+fn database_location(db : Option<&str>) -> Result<String, ColoredString>
+{
+    if let Some(db) = db {
+        return Ok(db.into());
+    }
+    let project = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)).ok().and_then(|c| c.audit);
+    project.or(load_global_config()?.audit)
+        .map(|audit| audit.database)
+        .ok_or(format!("{} No advisory database, pass --db or set database under [audit] in Config.toml", "Audit error:".red()).into())
+}
+
+/// Read every advisory below a directory, leaving out `.git`
+fn load_advisories(dir : &Path, advisories : &mut Vec<(PathBuf, AdvisoryFile)>) -> Result<(), ColoredString>
+{
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("{} Unable to read \"{}\": {e}", "Audit error:".red(), dir.to_string_lossy()))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if entry.file_name() != ".git" {
+                load_advisories(&path, advisories)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "toml") {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("{} Unable to read \"{}\": {e}", "Audit error:".red(), path.to_string_lossy()))?;
+            let advisory = toml::from_str(&contents)
+                .map_err(|e| e.to_string() + &format!("\nCould not read advisory \"{}\"", path.to_string_lossy()).red())?;
+            advisories.push((path, advisory));
+        }
+    }
+    Ok(())
+}
+
+fn print_finding(dep : &Dependency, advisory : &AdvisoryFile)
+{
+    let info = &advisory.advisory;
+    println!("{} {} {}", get_dep_filename(dep).unwrap_or_default().bold(), info.id.red().bold(), info.title);
+    if let Some(description) = &info.description {
+        println!("    {}", description.trim());
+    }
+    if let Some(date) = &info.date {
+        println!("    {} {date}", "Date:".bold());
+    }
+    if let Some(url) = &info.url {
+        println!("    {} {url}", "More:".bold());
+    }
+    match advisory.versions.patched.is_empty() {
+        true => println!("    {} no fixed version", "Patched:".bold()),
+        false => println!("    {} {}", "Patched:".bold(), advisory.versions.patched.join(" or ").green()),
+    }
+}
+
+/// Check every package in the resolved dependency tree against the advisory database.
+/// Fails if any dependency is affected by an advisory.
+pub fn audit(db : Option<&str>) -> Result<(), ColoredString>
+{
+    let location = database_location(db)?;
+    let dir = match is_git_location(&location) {
+        true => sync_git_repo(&location, "advisory database", "advisories")?,
+        false => PathBuf::from(&location),
+    };
+    let mut advisories = Vec::new();
+    load_advisories(&dir, &mut advisories)?;
+    debug("Audit", &format!("Read {} advisories from \"{}\"", advisories.len(), dir.to_string_lossy()));
+
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let deps = get_dep_list(&cfg)?;
+    let mut found = 0;
+    for dep in &deps {
+        let Ok(version) = semver::Version::parse(&dep.version) else {
+            warn("Audit", &format!("Skipping {} {}, its version isn't semver", dep.name, dep.version));
+            continue;
+        };
+        for (path, advisory) in advisories.iter().filter(|(_, a)| a.advisory.package == dep.name) {
+            if advisory.affects(&version, path)? {
+                print_finding(dep, advisory);
+                found += 1;
+            }
+        }
+    }
+
+    if found > 0 {
+        return Err(format!("{} {found} advisories affect the dependencies of this project", "Audit error:".red()).into());
+    }
+    success(&format!("No advisories affect the {} dependencies of this project", deps.len()));
+    Ok(())
+}
@@ -1,9 +1,9 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command};
 
 use crate::config::{Config, Dependency};
 use crate::logging::*;
-use crate::pdm::{add_dependency, get_dep_cfg, get_dep_filename, get_hc_filepath, table_to_dep};
+use crate::pdm::{ensure_dep_cached, entry_to_dep, get_dep_cfg, get_dep_dir, get_dep_list};
 use colored::{ColoredString, Colorize};
 
 fn run_hcc( command : String, args : Vec<String>) -> std::result::Result<String, colored::ColoredString> {
@@ -38,7 +38,7 @@ fn run_hcc( command : String, args : Vec<String>) -> std::result::Result<String,
             }
         },
         Err(e) => {
-            Err(format!("{} (Do you have hcc installed?)",e.to_string()).into())
+            Err(format!("{} (Do you have hcc installed?)",e).into())
         }
     }
 }
@@ -64,10 +64,7 @@ pub fn check_valid(config : &Config) -> std::result::Result<(), colored::Colored
 
 fn add_dep_to_source(dep : Dependency, source : &mut Vec<String>) -> Result<(), ColoredString>
 {
-    let cfg_path: PathBuf = {
-        let dir_name = get_dep_filename(&dep)?;
-        get_hc_filepath()?.join::<PathBuf>(dir_name.into())
-    };
+    let cfg_path: PathBuf = get_dep_dir(&dep)?;
 
     for infile in get_dep_cfg(dep)?.build.infiles{
         debug("add_dep_to_source", &format!("Adding {} to source", &infile));
@@ -80,38 +77,29 @@ fn add_dep_to_source(dep : Dependency, source : &mut Vec<String>) -> Result<(),
 }
 
 pub fn build(config : &Config) -> std::result::Result<(), colored::ColoredString> {
+    crate::cache::register_project()?;
+    build_from(config, Path::new(""))
+}
+
+/// Build with infiles relative to `base`, without registering the project with the cache.
+/// Dependencies are still resolved from the current project.
+pub fn build_from(config : &Config, base : &Path) -> std::result::Result<(), colored::ColoredString> {
     let mut args: Vec<String> = Vec::new();
+    crate::verify::verify_deps(&get_dep_list(config)?, crate::verify::on_mismatch(config))?;
 
     if let Some(deps) = config.dependencies.as_ref()
     {
         for depfile in deps{
-            args.push("-i".into());
-
-            let dep = table_to_dep(depfile.1.as_table().expect("Unable to create table from dependency"))?;
-
-            match std::fs::exists(&dep.source) {
-                Ok(true)=>{
-                    add_dep_to_source(dep, &mut args)?;
-                },
-                Ok(false) =>
-                {
-                    add_dependency(dep.source.clone())?;
-                    add_dep_to_source(dep, &mut args)?
-                },
-                _=>
-                {
-                    let full_path: PathBuf = get_hc_filepath()?.join::<PathBuf>(depfile.0.into());
-                    args.push(full_path.to_string_lossy().into());
-                }
-            }
-            
+            let dep = entry_to_dep(depfile.0, depfile.1)?;
+            ensure_dep_cached(&dep)?;
+            add_dep_to_source(dep, &mut args)?;
         }
     }
     
 
     for infile in &config.build.infiles{
         args.push("-i".into());
-        args.push(infile.into());
+        args.push(base.join(infile).to_string_lossy().into());
     }
 
     let out = run_hcc("build".into(), args)?;
@@ -125,7 +113,7 @@ pub fn run(config : &Config, params : Vec<String>) -> std::result::Result<(), co
 
     if let Some(deps) = config.dependencies.as_ref() {
         for dep_table in deps{
-            let dep = table_to_dep(dep_table.1.as_table().expect("Unable to create dependency table"))?;
+            let dep = entry_to_dep(dep_table.0, dep_table.1)?;
             
             add_dep_to_source(dep, &mut args)?
         }
@@ -0,0 +1,465 @@
+use std::path::{Path, PathBuf};
+
+use colored::{ColoredString, Colorize};
+use sha2::{Digest, Sha256};
+
+use crate::config::Dependency;
+use crate::logging::*;
+use crate::pdm::{get_cache_filepath, get_hc_filepath};
+use crate::registry::sha256_hex;
+
+/// Index of the package cache, stored at `packages/index.toml` in the cache directory (`~/.hc` by default).
+/// Maps each cached package to its directory, which is keyed by source and content so that
+/// two packages with the same name and version from different sources don't collide.
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct CacheIndex {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<CacheEntry>,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Clone)]
+pub struct CacheEntry {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    /// Commit of a git package, archive checksum of a registry package, or `path` for a local copy
+    pub id: String,
+    /// Directory name inside `packages`
+    pub dir: String,
+    /// When the package was fetched, in seconds since the Unix epoch
+    #[serde(default)]
+    pub fetched: u64,
+    /// `tree_checksum` of the files when they were fetched, checked by `gup verify` and `gup build`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// Directory holding cached packages and their index
+pub fn cache_root() -> Result<PathBuf, ColoredString>
+{
+    let root = get_cache_filepath()?.join("packages");
+    std::fs::create_dir_all(&root).map_err(|e| format!("Unable to create directory: {e}").red())?;
+    Ok(root)
+}
+
+fn index_path() -> Result<PathBuf, ColoredString>
+{
+    Ok(cache_root()?.join("index.toml"))
+}
+
+/// Exclusive lock on the cache, held while the index or package directories change.
+/// Released when dropped, or by the OS if gup dies.
+pub struct CacheLock {
+    _file: std::fs::File,
+}
+
+impl CacheLock {
+    pub fn acquire() -> Result<CacheLock, ColoredString>
+    {
+        let path = cache_root()?.join(".lock");
+        let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+            .map_err(|e| format!("{} Unable to open \"{}\": {e}", "Cache error:".red(), path.to_string_lossy()))?;
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(std::fs::TryLockError::WouldBlock) => {
+                info("Cache", "Waiting for another gup process to release the cache lock");
+                file.lock().map_err(|e| format!("{} Unable to lock the cache: {e}", "Cache error:".red()))?;
+            },
+            Err(std::fs::TryLockError::Error(e)) => return Err(format!("{} Unable to lock the cache: {e}", "Cache error:".red()).into()),
+        }
+        Ok(CacheLock { _file: file })
+    }
+}
+
+/// Scratch directory for fetching or verifying a package, unique to this process, removed on drop unless it was moved into the cache.
+/// It lives inside the cache so moving it into place is an atomic rename on the same filesystem.
+pub struct StagingDir {
+    pub path: PathBuf,
+}
+
+impl StagingDir {
+    pub fn new() -> Result<StagingDir, ColoredString>
+    {
+        static COUNT : std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let tmp = cache_root()?.join(".tmp");
+        std::fs::create_dir_all(&tmp).map_err(|e| format!("Unable to create directory: {e}").red())?;
+        let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        // the directory itself is created by whatever fills it (clone, copy or unpack)
+        Ok(StagingDir { path: tmp.join(format!("{}-{count}-{nanos}", std::process::id())) })
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self)
+    {
+        if self.path.exists() {
+            debug("Cache", &format!("Removing \"{}\"", self.path.to_string_lossy()));
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Cache directory name for a package: readable name and version, then a hash of source and content
+fn entry_dir(dep : &Dependency, id : &str) -> String
+{
+    let key = sha256_hex(format!("{}\n{id}", dep.source).as_bytes());
+    format!("{}-{}-{}", dep.name.chars().filter(|c| !c.is_whitespace()).collect::<String>(), dep.version, &key[..16])
+}
+
+impl CacheIndex {
+    pub fn load() -> Result<CacheIndex, ColoredString>
+    {
+        let path = index_path()?;
+        if !path.exists() {
+            return Ok(CacheIndex::default());
+        }
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{} {}", "Cache error:\n".red(), e))?;
+        toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read cache index".red()).into())
+    }
+
+    /// Write the index. Callers must hold the `CacheLock`; readers never see a partly written file.
+    pub fn save(&self) -> Result<(), ColoredString>
+    {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        let path = index_path()?;
+        let tmp = path.with_extension(format!("toml.{}", std::process::id()));
+        std::fs::write(&tmp, format!("# This file is generated by gup. Do not edit it by hand.\n{contents}"))
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|e| e.to_string().red())?;
+        Ok(())
+    }
+
+    /// Most recently cached entry for a dependency, of any content
+    pub fn find(&self, dep : &Dependency) -> Option<&CacheEntry>
+    {
+        self.packages.iter().rev().find(|e| e.name == dep.name && e.version == dep.version && e.source == dep.source)
+    }
+
+    /// Entry for a dependency with exactly this content
+    pub fn find_id(&self, dep : &Dependency, id : &str) -> Option<&CacheEntry>
+    {
+        self.packages.iter().find(|e| e.name == dep.name && e.version == dep.version && e.source == dep.source && e.id == id)
+    }
+}
+
+/// Directory of a dependency in the cache, if it has been cached
+pub fn cached_dir(dep : &Dependency) -> Result<Option<PathBuf>, ColoredString>
+{
+    let root = cache_root()?;
+    Ok(CacheIndex::load()?.find(dep).map(|e| root.join(&e.dir)).filter(|dir| dir.exists()))
+}
+
+/// Directory of a dependency with exactly this content, if it has been cached
+pub fn cached_dir_with_id(dep : &Dependency, id : &str) -> Result<Option<PathBuf>, ColoredString>
+{
+    let root = cache_root()?;
+    Ok(CacheIndex::load()?.find_id(dep, id).map(|e| root.join(&e.dir)).filter(|dir| dir.exists()))
+}
+
+/// Move a fetched package into the cache and record it in the index.
+/// If the same source and content is already cached (perhaps by a parallel gup), the staged copy is dropped instead.
+pub fn add_to_cache(dep : &Dependency, id : &str, staged : StagingDir) -> Result<PathBuf, ColoredString>
+{
+    let root = cache_root()?;
+    let _lock = CacheLock::acquire()?;
+    let mut index = CacheIndex::load()?;
+    let dir_name = entry_dir(dep, id);
+    let dir = root.join(&dir_name);
+    // the staged copy is fresh even when an existing directory is kept, so the checksum is always taken from it
+    let checksum = tree_checksum(&staged.path).map_err(|e| e.to_string().red())?;
+
+    if dir.exists() {
+        debug("Cache", &format!("{} {} from {} is already cached", dep.name, dep.version, dep.source));
+    } else {
+        debug("Cache", &format!("Caching {} {} from {} in \"{}\"", dep.name, dep.version, dep.source, dir.to_string_lossy()));
+        std::fs::rename(&staged.path, &dir).map_err(|e| e.to_string().red())?;
+    }
+
+    // the newest entry wins lookups without an id, so re-adding moves it to the end
+    index.packages.retain(|e| e.dir != dir_name);
+    index.packages.push(CacheEntry {
+        name: dep.name.clone(),
+        version: dep.version.clone(),
+        source: dep.source.clone(),
+        id: id.into(),
+        dir: dir_name,
+        fetched: now(),
+        checksum: Some(checksum),
+    });
+    index.save()?;
+    Ok(dir)
+}
+
+fn now() -> u64
+{
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Copy a directory tree, leaving out `.git`
+pub fn copy_tree(from : &Path, to : &Path) -> std::io::Result<()>
+{
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// sha256 over the relative paths and contents of every file in a tree, in sorted order
+pub fn tree_checksum(dir : &Path) -> std::io::Result<String>
+{
+    fn collect(root : &Path, dir : &Path, files : &mut Vec<PathBuf>) -> std::io::Result<()>
+    {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(root, &path, files)?;
+            } else {
+                files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+            }
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    collect(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(std::fs::read(dir.join(&file))?);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Total size in bytes of the files under a path
+pub fn dir_size(path : &Path) -> u64
+{
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => std::fs::read_dir(path)
+            .map(|entries| entries.flatten().map(|e| dir_size(&e.path())).sum())
+            .unwrap_or(0),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+fn format_size(bytes : u64) -> String
+{
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1048576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+/// Remove staging directories left behind by gup runs that died, skipping recent ones that may still be in use
+fn remove_stale_staging() -> Result<u64, ColoredString>
+{
+    let Ok(entries) = std::fs::read_dir(cache_root()?.join(".tmp")) else { return Ok(0) };
+    let mut freed = 0;
+    for entry in entries.flatten() {
+        let stale = entry.metadata().and_then(|m| m.modified()).ok()
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age.as_secs() > 60 * 60);
+        if stale {
+            freed += dir_size(&entry.path());
+            std::fs::remove_dir_all(entry.path()).map_err(|e| e.to_string().red())?;
+        }
+    }
+    Ok(freed)
+}
+
+/// Remove index entries matching a filter, and their directories. Returns the number removed and the bytes freed.
+pub fn remove_entries(filter : impl Fn(&CacheEntry) -> bool) -> Result<(usize, u64), ColoredString>
+{
+    let _lock = CacheLock::acquire()?;
+    let mut index = CacheIndex::load()?;
+    remove_from_index(&mut index, filter)
+}
+
+/// Remove entries matching a filter from an index loaded under the `CacheLock`, and their directories, then save it
+fn remove_from_index(index : &mut CacheIndex, filter : impl Fn(&CacheEntry) -> bool) -> Result<(usize, u64), ColoredString>
+{
+    let root = cache_root()?;
+    let (removed, kept) : (Vec<CacheEntry>, Vec<CacheEntry>) = index.packages.drain(..).partition(|e| filter(e));
+    index.packages = kept;
+
+    let mut freed = remove_stale_staging()?;
+    for entry in &removed {
+        let dir = root.join(&entry.dir);
+        debug("Cache", &format!("Removing {} {} from {}", entry.name, entry.version, entry.source));
+        freed += dir_size(&dir);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).map_err(|e| e.to_string().red())?;
+        }
+    }
+    index.save()?;
+    Ok((removed.len(), freed))
+}
+
+/// Print every cached package with its source and size
+pub fn list() -> Result<(), ColoredString>
+{
+    let root = cache_root()?;
+    let index = CacheIndex::load()?;
+    if index.packages.is_empty() {
+        info("Cache", "The cache is empty");
+    }
+    for entry in &index.packages {
+        println!("{} {} {} ({})", entry.name.bold(), entry.version.green(), entry.source, format_size(dir_size(&root.join(&entry.dir))));
+    }
+    Ok(())
+}
+
+/// Print the total size of the cache
+pub fn size() -> Result<(), ColoredString>
+{
+    let root = cache_root()?;
+    let count = CacheIndex::load()?.packages.len();
+    println!("{} packages, {}", count, format_size(dir_size(&root)));
+    Ok(())
+}
+
+/// Remove every cached package, or every cached version of one package
+pub fn clean(name : Option<&str>) -> Result<(), ColoredString>
+{
+    let (count, freed) = remove_entries(|e| name.is_none_or(|name| e.name == name))?;
+    success(&format!("Removed {count} packages, freeing {}", format_size(freed)));
+    Ok(())
+}
+
+/// Projects whose lockfiles and dependencies `gup cache gc` keeps, stored in `~/.hc/projects.toml`
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct Projects {
+    #[serde(default)]
+    pub projects: Vec<PathBuf>,
+}
+
+fn projects_path() -> Result<PathBuf, ColoredString>
+{
+    Ok(get_hc_filepath()?.join("projects.toml"))
+}
+
+fn load_projects() -> Result<Projects, ColoredString>
+{
+    let path = projects_path()?;
+    if !path.exists() {
+        return Ok(Projects::default());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{} {}", "Cache error:\n".red(), e))?;
+    toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read registered projects".red()).into())
+}
+
+/// Remember the current project, so `gup cache gc` keeps what it uses
+pub fn register_project() -> Result<(), ColoredString>
+{
+    let cwd = std::env::current_dir().and_then(|d| d.canonicalize()).map_err(|e| e.to_string())?;
+    if load_projects()?.projects.contains(&cwd) {
+        return Ok(());
+    }
+    let _lock = CacheLock::acquire()?;
+    // reload under the lock, another gup may have registered a project meanwhile
+    let mut projects = load_projects()?;
+    if !projects.projects.contains(&cwd) {
+        projects.projects.push(cwd);
+        save_projects(&projects)?;
+    }
+    Ok(())
+}
+
+/// Write projects.toml through a temporary file, so it is never seen half written. The caller holds the `CacheLock`.
+fn save_projects(projects : &Projects) -> Result<(), ColoredString>
+{
+    let contents = toml::to_string(projects).map_err(|e| e.to_string())?;
+    let path = projects_path()?;
+    let tmp = path.with_extension(format!("toml.{}", std::process::id()));
+    std::fs::write(&tmp, contents)
+        .and_then(|_| std::fs::rename(&tmp, &path))
+        .map_err(|e| e.to_string().red())?;
+    Ok(())
+}
+
+/// Whether a dependency table entry with a `source` refers to a cache entry
+fn source_dep_matches(value : &toml::Value, entry : &CacheEntry) -> bool
+{
+    value.as_table().is_some_and(|t| {
+        let get = |key : &str| t.get(key).and_then(|v| v.as_str());
+        get("source") == Some(&entry.source) && get("name") == Some(&entry.name) && get("version") == Some(&entry.version)
+    })
+}
+
+/// Remove cached packages that no registered project uses, and with `days`, any fetched more than that many days ago
+pub fn gc(days : Option<u64>) -> Result<(), ColoredString>
+{
+    let root = cache_root()?;
+    // held throughout, so packages cached and projects registered meanwhile are seen or wait for the collection
+    let _lock = CacheLock::acquire()?;
+    let mut index = CacheIndex::load()?;
+    let projects = load_projects()?;
+    let live : Vec<&PathBuf> = projects.projects.iter().filter(|p| p.join(crate::CONFIG_PATH).exists()).collect();
+    debug("Cache", &format!("{} registered projects", live.len()));
+
+    // packages locked by a project, or git and path dependencies in its Config.toml
+    let mut keep = vec![false; index.packages.len()];
+    let mut configs = Vec::new();
+    for project in &live {
+        let lock = crate::lock::Lockfile::load(&project.join(crate::LOCK_PATH))?;
+        for (i, entry) in index.packages.iter().enumerate() {
+            if lock.packages.iter().any(|p| p.name == entry.name && p.version == entry.version
+                && p.source == entry.source && p.checksum.as_ref().or(p.commit.as_ref()).or(p.integrity.as_ref()).is_none_or(|id| *id == entry.id)) {
+                keep[i] = true;
+            }
+        }
+        configs.push(project.join(crate::CONFIG_PATH));
+    }
+    // locked packages may have git or path dependencies of their own
+    configs.extend(index.packages.iter().enumerate()
+        .filter(|(i, _)| keep[*i])
+        .map(|(_, entry)| root.join(&entry.dir).join("Config.toml")));
+
+    // follow the dependencies of kept packages, newest entry first as lookups do
+    while let Some(config) = configs.pop() {
+        let Ok(cfg) = crate::config::create_config_from_path(&config) else { continue };
+        for value in cfg.dependencies.iter().flat_map(|d| d.values()) {
+            if let Some(i) = (0..index.packages.len()).rev().find(|i| source_dep_matches(value, &index.packages[*i]))
+                && !keep[i] {
+                keep[i] = true;
+                configs.push(root.join(&index.packages[i].dir).join("Config.toml"));
+            }
+        }
+    }
+
+    let cutoff = days.map(|days| now().saturating_sub(days * 24 * 60 * 60));
+    let dead : Vec<String> = index.packages.iter().enumerate()
+        .filter(|(i, entry)| !keep[*i] || cutoff.is_some_and(|cutoff| entry.fetched < cutoff))
+        .map(|(_, entry)| entry.dir.clone())
+        .collect();
+    let (count, freed) = remove_from_index(&mut index, |e| dead.contains(&e.dir))?;
+
+    // forget projects that no longer exist
+    if live.len() < projects.projects.len() {
+        save_projects(&Projects { projects: live.into_iter().cloned().collect() })?;
+    }
+    success(&format!("Removed {count} packages, freeing {}", format_size(freed)));
+    Ok(())
+}
@@ -1,5 +1,10 @@
+use std::path::PathBuf;
+
 use clap::*;
 use clap_verbosity_flag::*;
+
+use crate::doc::DocFormat;
+
 /// Args for initializing
 #[derive(Debug, Args)]
 pub struct InitGroup {
@@ -16,12 +21,41 @@ pub struct RunGroup {
     pub paramaters: Vec<String>,
 }
 
+/// Args for generating docs
+#[derive(Debug, Args)]
+pub struct DocGroup {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = DocFormat::Markdown)]
+    pub format: DocFormat,
+    /// Also document every package in the dependency tree
+    #[arg(long, action)]
+    pub with_deps: bool,
+    /// Serve HTML docs on localhost and regenerate them when an infile changes
+    #[arg(long, action)]
+    pub serve: bool,
+    /// Port for --serve
+    #[arg(long, default_value_t = 8000, requires = "serve")]
+    pub port: u16,
+    /// Report undocumented definitions instead of writing docs
+    #[arg(long, action)]
+    pub coverage: bool,
+    /// Fail if coverage is below [doc] coverage_threshold (implies --coverage)
+    #[arg(long, action)]
+    pub deny_missing: bool,
+}
+
 /// Args for adding a dependency
 
 #[derive(Debug, Args)]
 #[clap(group = ArgGroup::new("input")
     .required(true))]
 pub struct AddGroup {
+    /// Package from a registry, as NAME or NAME@VERSION_REQUIREMENT
+    #[arg(group = "input")]
+    pub package: Option<String>,
+    /// Registry to resolve the package from, instead of the default one
+    #[arg(long, requires = "package")]
+    pub registry: Option<String>,
     /// Arg URL
     #[arg(short, long, group = "input")]
     pub url: Option<String>,
@@ -33,6 +67,174 @@ pub struct AddGroup {
     pub tree : bool
 }
 
+/// Args for checking API compatibility
+#[derive(Debug, Args)]
+pub struct SemverCheckGroup {
+    /// Version (a git tag) or project path to compare against. Defaults to the latest earlier git tag
+    #[arg(short, long)]
+    pub baseline: Option<String>,
+}
+
+/// Args for publishing
+#[derive(Debug, Args)]
+pub struct PublishGroup {
+    /// Registry to publish to, instead of the default one
+    #[arg(long)]
+    pub registry: Option<String>,
+    /// Package and verify without writing to the registry
+    #[arg(long, action)]
+    pub dry_run: bool,
+    /// Skip building the packaged project before publishing
+    #[arg(long, action)]
+    pub no_verify: bool,
+    /// Sign the archive with the key from `gup key generate`
+    #[arg(long, action)]
+    pub sign: bool,
+}
+
+/// Args for storing a registry token
+#[derive(Debug, Args)]
+pub struct LoginGroup {
+    /// Registry the token is for, instead of the default one
+    #[arg(long)]
+    pub registry: Option<String>,
+    /// Token to store. Read from stdin if not given
+    #[arg(long)]
+    pub token: Option<String>,
+}
+
+/// Args for searching registries
+#[derive(Debug, Args)]
+pub struct SearchGroup {
+    /// Text to look for in package names and descriptions
+    pub query: String,
+    /// Only search this registry
+    #[arg(long)]
+    pub registry: Option<String>,
+}
+
+/// Args for showing a package
+#[derive(Debug, Args)]
+pub struct InfoGroup {
+    /// Package name
+    pub name: String,
+    /// Registry to look in, instead of the default one
+    #[arg(long)]
+    pub registry: Option<String>,
+}
+
+/// Args for yanking a published version
+#[derive(Debug, Args)]
+pub struct YankGroup {
+    /// Version to yank, as NAME@VERSION
+    pub package: String,
+    /// Registry the version is published to, instead of the default one
+    #[arg(long)]
+    pub registry: Option<String>,
+    /// Make a yanked version selectable again
+    #[arg(long, action)]
+    pub undo: bool,
+}
+
+/// Args for deprecating a package
+#[derive(Debug, Args)]
+pub struct DeprecateGroup {
+    /// Package name
+    pub name: String,
+    /// Message shown to users of the package, e.g. what to use instead
+    #[arg(required_unless_present = "undo")]
+    pub message: Option<String>,
+    /// Registry the package is published to, instead of the default one
+    #[arg(long)]
+    pub registry: Option<String>,
+    /// Remove the deprecation
+    #[arg(long, action, conflicts_with = "message")]
+    pub undo: bool,
+}
+
+/// Args for removing cached packages
+#[derive(Debug, Args)]
+pub struct CacheCleanGroup {
+    /// Only remove versions of this package
+    pub name: Option<String>,
+}
+
+/// Args for garbage collecting the cache
+#[derive(Debug, Args)]
+pub struct CacheGcGroup {
+    /// Also remove packages fetched more than this many days ago, even if they are used
+    #[arg(long, value_name = "DAYS")]
+    pub older_than: Option<u64>,
+}
+
+/// Cache subcommands
+#[derive(Debug, Subcommand)]
+pub enum CacheCommands {
+    /// List cached packages with their sources and sizes
+    List,
+    /// Show the total size of the cache
+    Size,
+    /// Remove every cached package, or the versions of one package
+    Clean(CacheCleanGroup),
+    /// Remove cached packages that no registered project uses
+    Gc(CacheGcGroup),
+}
+
+/// Args for managing the package cache
+#[derive(Debug, Args)]
+pub struct CacheGroup {
+    #[command(subcommand)]
+    pub command: CacheCommands,
+}
+
+/// Args for generating a signing key
+#[derive(Debug, Args)]
+pub struct KeyGenerateGroup {
+    /// Replace an existing key
+    #[arg(long, action)]
+    pub force: bool,
+}
+
+/// Signing key subcommands
+#[derive(Debug, Subcommand)]
+pub enum KeyCommands {
+    /// Create a signing key and print its public key
+    Generate(KeyGenerateGroup),
+    /// Print the public key of the signing key
+    Show,
+}
+
+/// Args for managing the signing key
+#[derive(Debug, Args)]
+pub struct KeyGroup {
+    #[command(subcommand)]
+    pub command: KeyCommands,
+}
+
+/// Args for auditing dependencies
+#[derive(Debug, Args)]
+pub struct AuditGroup {
+    /// Directory or git URL of the advisory database, instead of the one under [audit]
+    #[arg(long)]
+    pub db: Option<String>,
+}
+
+/// Args for vendoring dependencies
+#[derive(Debug, Args)]
+pub struct VendorGroup {
+    /// Directory to copy the dependencies into
+    #[arg(default_value = "vendor")]
+    pub dir: String,
+}
+
+/// Args for fetching dependencies
+#[derive(Debug, Args)]
+pub struct FetchGroup {
+    /// Number of packages to download at once. Defaults to the number of CPUs
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+}
+
 /// Subcommands
 #[derive(Debug, Subcommand)]
 pub enum Commands {
@@ -45,14 +247,40 @@ pub enum Commands {
     /// Initialize a new Halcyon project in the current directory
     Init(InitGroup),
     /// Create documentation based off line comments
-    Doc,
+    Doc(DocGroup),
     /// Add a dependency to your project
     Add(AddGroup),
     /// Update dependencies to the most recent versions
     Update,
     Version,
     /// Show dependency tree
-    Tree
+    Tree,
+    /// Compare documented signatures against a previous version and propose a version bump
+    SemverCheck(SemverCheckGroup),
+    /// Package the project and add it to a registry
+    Publish(PublishGroup),
+    /// Store a token for publishing to an HTTP registry
+    Login(LoginGroup),
+    /// Search configured registries for packages
+    Search(SearchGroup),
+    /// Show versions, dependencies and cache status of a registry package
+    Info(InfoGroup),
+    /// Download every dependency into the cache without building
+    Fetch(FetchGroup),
+    /// Manage the key used to sign published packages
+    Key(KeyGroup),
+    /// Check cached dependencies against the checksums recorded when they were fetched
+    Verify,
+    /// Check dependencies against a database of security advisories
+    Audit(AuditGroup),
+    /// Copy every dependency into the project and build from the copies
+    Vendor(VendorGroup),
+    /// Inspect and clean the package cache
+    Cache(CacheGroup),
+    /// Stop a published version from being picked for new resolutions
+    Yank(YankGroup),
+    /// Mark a published package as deprecated
+    Deprecate(DeprecateGroup),
 }
 
 #[derive(Parser, Debug)]
@@ -63,4 +291,16 @@ pub struct CmdArgs {
     /// Verbosity
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
+    /// Directory for cached packages and registry clones, instead of GUP_HOME or ~/.hc
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Never use the network, and fail if a dependency isn't cached
+    #[arg(long, global = true, action)]
+    pub offline: bool,
+    /// Fail if Gup.lock is missing a dependency from Config.toml or would change
+    #[arg(long, global = true, action)]
+    pub locked: bool,
+    /// Same as --offline --locked
+    #[arg(long, global = true, action)]
+    pub frozen: bool,
 }
\ No newline at end of file
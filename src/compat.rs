@@ -0,0 +1,274 @@
+use std::path::{Component, Path, PathBuf};
+
+use colored::{ColoredString, Colorize};
+use semver::Version;
+
+use crate::config::{create_config_from_path, Config};
+use crate::doc::DocSet;
+use crate::logging::*;
+use crate::parse::{extract_docs, parse_source, DocWarning};
+
+/// How big a version bump a set of API changes needs
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+/// A documented item, identified by module and title
+#[derive(Clone)]
+pub struct ApiItem {
+    pub module: String,
+    pub title: String,
+    pub signature: String,
+}
+
+/// Differences between the documented API of two versions
+pub struct ApiChanges {
+    pub removed: Vec<ApiItem>,
+    /// (baseline, current) signatures of items whose signature changed
+    pub changed: Vec<(ApiItem, ApiItem)>,
+    pub added: Vec<ApiItem>,
+}
+
+impl ApiChanges {
+    pub fn bump(&self) -> Bump
+    {
+        if !self.removed.is_empty() || !self.changed.is_empty() {
+            Bump::Major
+        } else if !self.added.is_empty() {
+            Bump::Minor
+        } else {
+            Bump::Patch
+        }
+    }
+}
+
+fn api_items(docs : &DocSet) -> Vec<ApiItem>
+{
+    let mut items : Vec<ApiItem> = Vec::new();
+    for module in &docs.modules {
+        for item in &module.items {
+            // the first item with a given name wins, like links do
+            if !items.iter().any(|i| i.module == module.name && i.title == item.title) {
+                items.push(ApiItem { module: module.name.clone(), title: item.title.clone(), signature: item.signature.clone() });
+            }
+        }
+    }
+    items
+}
+
+/// Compare the documented signatures of two versions
+pub fn compare(baseline : &DocSet, current : &DocSet) -> ApiChanges
+{
+    let old = api_items(baseline);
+    let new = api_items(current);
+    let find = |list : &[ApiItem], item : &ApiItem| list.iter().position(|i| i.module == item.module && i.title == item.title);
+
+    let mut changes = ApiChanges { removed: Vec::new(), changed: Vec::new(), added: Vec::new() };
+    for item in &old {
+        match find(&new, item) {
+            None => changes.removed.push(item.clone()),
+            Some(i) if new[i].signature != item.signature => changes.changed.push((item.clone(), new[i].clone())),
+            Some(_) => {},
+        }
+    }
+    for item in &new {
+        if find(&old, item).is_none() {
+            changes.added.push(item.clone());
+        }
+    }
+    changes
+}
+
+/// The smallest version after `baseline` that allows a bump. Before 1.0.0, breaking changes bump the minor version and additions the patch version.
+pub fn propose_version(baseline : &Version, bump : &Bump) -> Version
+{
+    match (baseline.major, bump) {
+        (0, Bump::Major) | (1.., Bump::Minor) => Version::new(baseline.major, baseline.minor + 1, 0),
+        (0, _) | (1.., Bump::Patch) => Version::new(baseline.major, baseline.minor, baseline.patch + 1),
+        (1.., Bump::Major) => Version::new(baseline.major + 1, 0, 0),
+    }
+}
+
+/// Strip `.` components so paths like `./main.hc` can be looked up in a git tree
+fn normalize(path : &Path) -> PathBuf
+{
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+/// Docs of this project as of a git tag `v{version}` or `{version}`
+fn docs_from_git(version : &Version, warnings : &mut Vec<DocWarning>) -> Result<Option<DocSet>, ColoredString>
+{
+    let Ok(repo) = git2::Repository::discover(".") else { return Ok(None) };
+    let Some(tag) = [format!("refs/tags/v{version}"), format!("refs/tags/{version}")]
+        .iter()
+        .find_map(|r| repo.revparse_single(r).ok()) else { return Ok(None) };
+    let tree = tag.peel_to_tree().map_err(|e| e.to_string())?;
+
+    // the project may live in a subdirectory of the repository
+    let workdir = repo.workdir().ok_or("Repository has no working directory".red())?;
+    let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+    let prefix = cwd.canonicalize().ok()
+        .and_then(|cwd| workdir.canonicalize().ok().and_then(|w| cwd.strip_prefix(w).ok().map(PathBuf::from)))
+        .unwrap_or_default();
+
+    let read = |path : &Path| -> Result<String, ColoredString> {
+        let full_path = prefix.join(normalize(path));
+        let blob = tree.get_path(&full_path)
+            .and_then(|entry| entry.to_object(&repo))
+            .and_then(|object| object.peel_to_blob())
+            .map_err(|e| format!("Unable to read {} at {version}: {e}", full_path.to_string_lossy()))?;
+        String::from_utf8(blob.content().to_vec()).map_err(|e| e.to_string().into())
+    };
+
+    let cfg : Config = toml::from_str(&read(Path::new(crate::CONFIG_PATH))?)
+        .map_err(|e| e.to_string() + &"\nCould not create baseline config".red())?;
+    let mut modules = Vec::new();
+    for infile in &cfg.build.infiles {
+        modules.extend(parse_source(&read(Path::new(infile))?, infile, warnings));
+    }
+    Ok(Some(DocSet {
+        package: cfg.package.as_ref().map(Into::into),
+        modules,
+        dependencies: Vec::new(),
+    }))
+}
+
+/// Highest version tagged in git that is lower than `current`
+fn latest_tagged_version(current : &Version) -> Option<Version>
+{
+    let repo = git2::Repository::discover(".").ok()?;
+    let tags = repo.tag_names(None).ok()?;
+    tags.iter()
+        .flatten()
+        .filter_map(|t| Version::parse(t.strip_prefix('v').unwrap_or(t)).ok())
+        .filter(|v| v < current)
+        .max()
+}
+
+/// Load the baseline docs from a project path, or from a version tagged in git.
+/// Returns the docs, their version and where they came from.
+fn load_baseline(baseline : Option<String>, current : &Version, warnings : &mut Vec<DocWarning>) -> Result<(DocSet, Version, String), ColoredString>
+{
+    if let Some(path) = baseline.as_ref().map(PathBuf::from).filter(|p| p.join("Config.toml").exists()) {
+        let cfg = create_config_from_path(&path.join("Config.toml"))?;
+        let version = cfg.package.as_ref()
+            .and_then(|p| Version::parse(&p.version).ok())
+            .ok_or(format!("{} Baseline at {} has no valid [package].version", "Semver error:".red(), path.to_string_lossy()))?;
+        return Ok((extract_docs(&cfg, &path, warnings)?, version, path.to_string_lossy().into()));
+    }
+
+    let version = match baseline {
+        Some(version) => Version::parse(version.strip_prefix('v').unwrap_or(&version))
+            .map_err(|e| format!("{} \"{version}\" is neither a project directory nor a version: {e}", "Semver error:".red()))?,
+        None => latest_tagged_version(current)
+            .ok_or(format!("{} No earlier version tagged in git, pass --baseline", "Semver error:".red()))?,
+    };
+
+    // only git tags are trusted to be this project: a cached package of the same name may be another one
+    match docs_from_git(&version, warnings)? {
+        Some(docs) => Ok((docs, version, String::from("git"))),
+        None => Err(format!("{} Version {version} not found in git tags", "Semver error:".red()).into()),
+    }
+}
+
+fn print_item(label : ColoredString, item : &ApiItem)
+{
+    println!("    {label} {}.{} : {}", item.module, item.title, item.signature);
+}
+
+pub fn semver_check(baseline : Option<String>) -> Result<(), ColoredString>
+{
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let pack = cfg.package.as_ref().ok_or(format!("{} Config has no [package]", "Semver error:".red()))?;
+    let current_version = Version::parse(&pack.version)
+        .map_err(|e| format!("{} Invalid [package].version \"{}\": {e}", "Semver error:".red(), pack.version))?;
+
+    let mut warnings = Vec::new();
+    let current = extract_docs(&cfg, Path::new("."), &mut warnings)?;
+    let (old, baseline_version, origin) = load_baseline(baseline, &current_version, &mut warnings)?;
+    for warning in &warnings {
+        warn("Semver", &warning.to_string());
+    }
+
+    info("Semver", &format!("Comparing {} {} against {baseline_version} ({origin})", pack.name, pack.version));
+    let changes = compare(&old, &current);
+    for item in &changes.removed {
+        print_item("removed".red(), item);
+    }
+    for (old, new) in &changes.changed {
+        println!("    {} {}.{}", "changed".yellow(), new.module, new.title);
+        println!("        - {}", old.signature);
+        println!("        + {}", new.signature);
+    }
+    for item in &changes.added {
+        print_item("added".green(), item);
+    }
+
+    let bump = changes.bump();
+    let proposed = propose_version(&baseline_version, &bump);
+    println!("{} {proposed} ({bump:?} bump from {baseline_version})", "Proposed version:".bold());
+    if current_version < proposed {
+        warn("Semver", &format!("[package].version is {current_version}, which is lower than the proposed {proposed}"));
+    } else {
+        success(&format!("[package].version {current_version} is compatible with the changes"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(source : &str) -> DocSet
+    {
+        DocSet { package: None, modules: parse_source(source, "lib.hc", &mut Vec::new()), dependencies: Vec::new() }
+    }
+
+    fn titles(items : &[ApiItem]) -> Vec<String>
+    {
+        items.iter().map(|i| format!("{}.{}", i.module, i.title)).collect()
+    }
+
+    const BASELINE : &str = "(* @title: keep\n   @signature: int *)\n(* @title: change\n   @signature: int *)\n(* @title: drop\n   @signature: int *)\n\
+        module Geo =\n  (* @title: keep\n     @signature: float *)\nend\n";
+
+    #[test]
+    fn compare_finds_removed_changed_and_added_items()
+    {
+        let current = docs("(* @title: keep\n   @signature: int *)\n(* @title: change\n   @signature: int -> int *)\n(* @title: new\n   @signature: int *)\n\
+            module Geo =\n  (* @title: keep\n     @signature: float *)\nend\n");
+        let changes = compare(&docs(BASELINE), &current);
+        assert_eq!(titles(&changes.removed), vec!["lib.drop"]);
+        assert_eq!(changes.changed.iter().map(|(old, new)| (old.signature.as_str(), new.signature.as_str())).collect::<Vec<_>>(),
+            vec![("int", "int -> int")]);
+        assert_eq!(titles(&changes.added), vec!["lib.new"]);
+        assert_eq!(changes.bump(), Bump::Major);
+    }
+
+    #[test]
+    fn additions_are_minor_and_no_changes_a_patch()
+    {
+        let added = docs(&format!("{BASELINE}(* @title: extra\n   @signature: int *)\n"));
+        assert_eq!(compare(&docs(BASELINE), &added).bump(), Bump::Minor);
+        assert_eq!(compare(&docs(BASELINE), &docs(BASELINE)).bump(), Bump::Patch);
+        // items are matched within their module, so the same title elsewhere doesn't count
+        let moved = docs("(* @title: change\n   @signature: int *)\n(* @title: drop\n   @signature: int *)\n(* @title: keep\n   @signature: float *)\n\
+            module Geo =\n  (* @title: keep\n     @signature: int *)\nend\n");
+        assert_eq!(compare(&docs(BASELINE), &moved).changed.len(), 2);
+    }
+
+    #[test]
+    fn proposed_versions_follow_semver()
+    {
+        let cases = [
+            ("0.3.2", Bump::Major, "0.4.0"), ("0.3.2", Bump::Minor, "0.3.3"), ("0.3.2", Bump::Patch, "0.3.3"),
+            ("1.2.3", Bump::Major, "2.0.0"), ("1.2.3", Bump::Minor, "1.3.0"), ("1.2.3", Bump::Patch, "1.2.4"),
+        ];
+        for (baseline, bump, expected) in cases {
+            assert_eq!(propose_version(&Version::parse(baseline).unwrap(), &bump).to_string(), expected, "{baseline} {bump:?}");
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use colored::{ColoredString, Colorize};
@@ -11,9 +12,27 @@ use crate::logging::*;
 pub struct Config {
     pub package: Option<Package>,
     pub build: Build,
+    pub doc: Option<Doc>,
+    /// Registry name to location (a directory or git URL)
+    pub registries: Option<BTreeMap<String, String>>,
+    pub vendor: Option<Vendor>,
+    pub verify: Option<Verify>,
+    pub signing: Option<Signing>,
+    pub audit: Option<Audit>,
     pub dependencies: Option<Table>,
 }
 
+/// Settings shared by every project, read from `~/.hc/config.toml`
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct GlobalConfig {
+    /// Registry name to location (a directory or git URL)
+    pub registries: Option<BTreeMap<String, String>>,
+    pub signing: Option<Signing>,
+    pub audit: Option<Audit>,
+}
+
 //config file struct
 #[derive(serde::Deserialize)]
 #[derive(serde::Serialize)]
@@ -23,15 +42,75 @@ pub struct Build{
     pub docfile: Option<String>
 }
 
+//config file struct
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct Doc {
+    /// Minimum documented percentage for `gup doc --deny-missing`
+    pub coverage_threshold: Option<f64>,
+    /// Template used for markdown and HTML docs instead of the built in layout
+    pub template: Option<String>,
+}
+
+/// Written by `gup vendor`, makes dependencies resolve to the vendored copies
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct Vendor {
+    /// Directory holding the vendored packages and their `vendor.toml` manifest
+    pub directory: String,
+}
+
+/// How `gup build` and `gup verify` treat cached packages whose files changed since they were fetched
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct Verify {
+    #[serde(default)]
+    pub on_mismatch: OnMismatch,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMismatch {
+    /// Fail, naming the modified packages
+    #[default]
+    Error,
+    /// Remove the modified packages from the cache and fetch them again
+    Refetch,
+}
+
+/// Which publishers' signatures registry packages are checked against
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct Signing {
+    /// Reject registry packages that aren't signed by a trusted key. Config.toml overrides the global config
+    pub require: Option<bool>,
+    /// Hex encoded ed25519 public keys, as printed by `gup key generate`. Both configs' keys are trusted
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+}
+
+/// Where `gup audit` reads security advisories from
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct Audit {
+    /// Directory or git URL of the advisory database. Config.toml overrides the global config
+    pub database: String,
+}
+
 #[derive(serde::Deserialize)]
 #[derive(serde::Serialize)]
 pub struct Package {
     pub name: String,
     pub version: String,
+    /// One line summary shown by `gup search` and `gup info`
+    pub description: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
 #[derive(serde::Serialize)]
+#[derive(Clone)]
 pub struct Dependency {
     pub name: String,
     pub version: String,
@@ -42,7 +121,7 @@ pub fn create_config_from_path(path : &PathBuf) -> std::result::Result<Config, C
 {
     debug("create_config_from_path",&format!("Creating config from \"{}\"", path.to_string_lossy()));
     let cfgfile = std::fs::read_to_string(path)
-        .map_err(|e| format!("{} {}", "Config error:\n".red(), e.to_string()))?;
+        .map_err(|e| format!("{} {}", "Config error:\n".red(), e))?;
     let cfg : Config = toml::from_str(&cfgfile).map_err(|e| e.to_string() + &"\nCould not create config".red())?;
     debug("create_config_from_path",&format!("Validating config: {}", path.to_string_lossy()));
     validate_config(&cfg)?;
@@ -62,11 +141,30 @@ pub fn create_config(ins : Vec<String>, out : String, dfile : Option<String>, de
 
         },
         dependencies: deps,
+        doc: None,
+        registries: None,
+        vendor: None,
+        verify: None,
+        signing: None,
+        audit: None,
         package: None
     };
     Ok(cfg)
 }
 
+/// Read the global config, or the defaults if there is none
+pub fn load_global_config() -> Result<GlobalConfig, ColoredString>
+{
+    let path = crate::pdm::get_hc_filepath()?.join("config.toml");
+    if !path.exists() {
+        return Ok(GlobalConfig::default());
+    }
+    debug("load_global_config",&format!("Reading global config from \"{}\"", path.to_string_lossy()));
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{} {}", "Config error:\n".red(), e))?;
+    toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read global config".red()).into())
+}
+
 pub fn validate_config(cfg : &Config) -> Result<(), ColoredString>
 {
     //check infiles for errors
@@ -90,14 +188,14 @@ pub fn validate_config(cfg : &Config) -> Result<(), ColoredString>
         _ => return std::result::Result::Err(format!("{}: {} \"{}\"","Config error".red(), "Invalid output filename:", &cfg.build.outfile).into()),
     }
 
-    if cfg.build.infiles.len() < 1
+    if cfg.build.infiles.is_empty()
     {
-        return std::result::Result::Err(String::from(format!("{} {}", "Config Error:".red(), "Please provide one or more input files!")).into())
+        return std::result::Result::Err(format!("{} {}", "Config Error:".red(), "Please provide one or more input files!").into())
     }
 
     if cfg.build.outfile.is_empty()
     {
-        return std::result::Result::Err(String::from(format!("{} {}", "Config Error:".red(), "Please provide exactly one output file!")).into())
+        return std::result::Result::Err(format!("{} {}", "Config Error:".red(), "Please provide exactly one output file!").into())
     }
 
     
@@ -112,6 +210,13 @@ pub fn validate_config(cfg : &Config) -> Result<(), ColoredString>
             }
         }
     }
+
+    //check coverage threshold if it exists
+    if let Some(threshold) = cfg.doc.as_ref().and_then(|d| d.coverage_threshold)
+        && !(0.0..=100.0).contains(&threshold)
+    {
+        return std::result::Result::Err(format!("{}: {} \"{}\"","Config error:".red(), "Coverage threshold must be between 0 and 100:", threshold).into())
+    }
      
 
     Ok(())
@@ -126,6 +231,30 @@ pub fn write_config( cfg : &Config, path : String ) -> Result<(), ColoredString>
     Ok(())
 }
 
+/// Add a registry dependency like `name = "^1.0"`, or a table with a `registry` if it is not the default one
+pub fn add_registry_dep_to_config (name : &str, requirement : &str, registry : Option<&str>, config_path : &str) -> Result<(), ColoredString>
+{
+    let config = create_config_from_path(&config_path.into())?;
+    let mut new_deps = config.dependencies.clone().unwrap_or_default();
+
+    let value = match registry {
+        None => toml::Value::String(requirement.into()),
+        Some(registry) => {
+            let mut new_dep_table = Table::new();
+            new_dep_table.insert("version".into(), toml::Value::String(requirement.into()));
+            new_dep_table.insert("registry".into(), toml::Value::String(registry.into()));
+            toml::Value::Table(new_dep_table)
+        }
+    };
+    new_deps.insert(name.into(), value);
+
+    let new_config = Config {
+        dependencies: Some(new_deps),
+        ..config
+    };
+    write_config(&new_config, config_path.into())
+}
+
 pub fn add_dep_to_config (dep : Dependency, config_path : &str) -> Result<(), ColoredString>
 {
     let config = create_config_from_path(&config_path.into())?;
@@ -140,7 +269,7 @@ pub fn add_dep_to_config (dep : Dependency, config_path : &str) -> Result<(), Co
     new_dep_table.insert("version".into(), toml::Value::String(dep.version));
     new_dep_table.insert("source".into(), toml::Value::String(dep.source));
 
-    new_deps.insert(dep.name.into(), toml::Value::Table(new_dep_table));
+    new_deps.insert(dep.name, toml::Value::Table(new_dep_table));
 
     let new_config = Config {
         dependencies: Some(new_deps),
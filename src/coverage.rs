@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use colored::Colorize;
+use regex::Regex;
+
+use crate::config::Config;
+use crate::parse::{in_comment, parse_doc_block, scan_comments, DocWarning, ModuleMap};
+
+/// Matches the start of a definition like `let name` or `type name`
+static DEFINITION: LazyLock<Regex> = LazyLock::new(||
+    Regex::new(r"(?m)^([ \t]*)(?:let|type|fun|val)\s+(?:rec\s+)?([A-Za-z_][A-Za-z0-9_']*)").unwrap());
+
+/// A definition with no doc block
+pub struct Missing {
+    pub name: String,
+    pub line: usize,
+}
+
+/// Documentation coverage of one module
+pub struct ModuleCoverage {
+    pub name: String,
+    pub file: String,
+    pub total: usize,
+    pub missing: Vec<Missing>,
+}
+
+impl ModuleCoverage {
+    pub fn documented(&self) -> usize
+    {
+        self.total - self.missing.len()
+    }
+
+    pub fn percent(&self) -> f64
+    {
+        percent(self.documented(), self.total)
+    }
+}
+
+fn percent(documented : usize, total : usize) -> f64
+{
+    if total == 0 { 100.0 } else { documented as f64 * 100.0 / total as f64 }
+}
+
+/// A top-level definition found in a source file
+struct Definition {
+    name: String,
+    module: String,
+    /// Byte offset of the start of the line
+    start: usize,
+    line: usize,
+    indent: usize,
+}
+
+/// Find the top-level definitions in one source file and check which ones have a doc block.
+/// A definition counts as documented if a doc block directly precedes it, or a doc block in the same module has its name as the title.
+pub fn source_coverage(source : &str, file : &str, warnings : &mut Vec<DocWarning>) -> Vec<ModuleCoverage>
+{
+    let comments = scan_comments(source, file, warnings);
+    let modules_at = ModuleMap::new(source, file, &comments);
+
+    // (end of the block, module, title) of each doc block
+    let blocks : Vec<(usize, String, String)> = comments.iter()
+        .filter_map(|c| parse_doc_block(c, file, warnings).map(|item| (c.span.end, modules_at.name_at(c.span.start), item.title)))
+        .collect();
+
+    let definitions : Vec<Definition> = DEFINITION.captures_iter(source)
+        .filter(|c| !in_comment(&comments, c.get(0).unwrap().start()))
+        .map(|c| {
+            let start = c.get(0).unwrap().start();
+            Definition {
+                name: c[2].to_string(),
+                module: modules_at.name_at(start),
+                start,
+                line: source[..start].matches('\n').count() + 1,
+                indent: c[1].len(),
+            }
+        })
+        .collect();
+
+    let mut modules : Vec<ModuleCoverage> = Vec::new();
+    for def in &definitions {
+        // nested definitions are indented further than the module's outermost ones
+        let top_indent = definitions.iter().filter(|d| d.module == def.module).map(|d| d.indent).min().unwrap_or(0);
+        if def.indent > top_indent {
+            continue;
+        }
+
+        let documented = blocks.iter().any(|(end, module, title)|
+            (*module == def.module && *title == def.name)
+            || (*end <= def.start && source[*end..def.start].trim().is_empty()));
+
+        let module = match modules.iter_mut().find(|m| m.name == def.module) {
+            Some(module) => module,
+            None => {
+                modules.push(ModuleCoverage { name: def.module.clone(), file: file.into(), total: 0, missing: Vec::new() });
+                modules.last_mut().unwrap()
+            }
+        };
+        module.total += 1;
+        if !documented {
+            module.missing.push(Missing { name: def.name.clone(), line: def.line });
+        }
+    }
+    modules
+}
+
+/// Coverage of every infile in a config
+pub fn coverage(cfg : &Config, warnings : &mut Vec<DocWarning>) -> Result<Vec<ModuleCoverage>, String>
+{
+    let mut modules = Vec::new();
+    for path in &cfg.build.infiles {
+        let source = std::fs::read_to_string(PathBuf::from(path))
+            .map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+        modules.extend(source_coverage(&source, path, warnings));
+    }
+    Ok(modules)
+}
+
+/// Percentage of definitions documented across all modules
+pub fn total_percent(modules : &[ModuleCoverage]) -> f64
+{
+    let total = modules.iter().map(|m| m.total).sum();
+    let documented = modules.iter().map(|m| m.documented()).sum();
+    percent(documented, total)
+}
+
+pub fn print_coverage(modules : &[ModuleCoverage])
+{
+    for module in modules {
+        println!("{} {} ({}): {}/{} documented ({:.1}%)",
+            "Module".bold(), module.name, module.file, module.documented(), module.total, module.percent());
+        for missing in &module.missing {
+            println!("    {} {} ({}:{})", "missing".yellow(), missing.name, module.file, missing.line);
+        }
+    }
+    println!("{} {:.1}%", "Total coverage:".bold(), total_percent(modules));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(source : &str) -> Vec<ModuleCoverage>
+    {
+        source_coverage(source, "shapes.hc", &mut Vec::new())
+    }
+
+    #[test]
+    fn counts_documented_definitions()
+    {
+        let modules = coverage("(* @title: area\n   @signature: float *)\nlet area = 1.0\nlet perimeter = 2.0\n\n\
+            let volume = 3.0\n(* @title: volume\n   @signature: float *)\n");
+        assert_eq!(modules.len(), 1);
+        assert_eq!((modules[0].total, modules[0].documented()), (3, 2));
+        assert_eq!(modules[0].missing.iter().map(|m| (m.name.as_str(), m.line)).collect::<Vec<_>>(), vec![("perimeter", 4)]);
+        assert!((total_percent(&modules) - 200.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nested_definitions_are_not_counted()
+    {
+        let modules = coverage("let outer x =\n  let inner = x in\n  inner\n");
+        assert_eq!(modules[0].total, 1);
+        assert_eq!(modules[0].missing[0].name, "outer");
+    }
+
+    #[test]
+    fn definitions_outside_modules_group_under_the_file_stem()
+    {
+        let modules = coverage("let a = 1\nmodule Circle =\n  let b = 2\nend\nlet c = 3\n");
+        let totals : Vec<(&str, usize)> = modules.iter().map(|m| (m.name.as_str(), m.total)).collect();
+        assert_eq!(totals, vec![("shapes", 2), ("Circle", 1)]);
+        assert_eq!(total_percent(&[]), 100.0);
+    }
+}
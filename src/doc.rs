@@ -0,0 +1,427 @@
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use crate::config::Package;
+
+/// Output formats supported by `gup doc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DocFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl DocFormat {
+    /// File extension used for docs written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DocFormat::Markdown => "md",
+            DocFormat::Json => "json",
+            DocFormat::Html => "html",
+        }
+    }
+}
+
+/// Documentation for a whole package, shared by every formatter
+#[derive(serde::Serialize)]
+pub struct DocSet {
+    pub package: Option<DocPackage>,
+    pub modules: Vec<DocModule>,
+    /// Docs for every package in the dependency tree, when generated with `--with-deps`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<DocSet>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DocPackage {
+    pub name: String,
+    pub version: String,
+}
+
+/// Documentation for one Halcyon module, or for a source file with no module declaration
+#[derive(serde::Serialize)]
+pub struct DocModule {
+    pub name: String,
+    pub file: String,
+    pub items: Vec<DocItem>,
+}
+
+/// A single documented item
+#[derive(serde::Serialize)]
+pub struct DocItem {
+    pub title: String,
+    pub signature: String,
+    pub description: String,
+    pub params: Vec<DocParam>,
+    pub returns: Option<String>,
+    /// Version the item was introduced in
+    pub since: Option<String>,
+    /// Deprecation notice. Empty if the item is deprecated without a message
+    pub deprecated: Option<String>,
+    /// Cross-references to other items
+    pub see: Vec<String>,
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct DocParam {
+    pub name: String,
+    pub description: String,
+}
+
+impl From<&Package> for DocPackage {
+    fn from(pack: &Package) -> Self {
+        DocPackage {
+            name: pack.name.clone(),
+            version: pack.version.clone(),
+        }
+    }
+}
+
+/// Render docs in the given format, using a template for markdown and HTML if one is given
+pub fn render(docs : &DocSet, format : DocFormat, template : Option<&Path>) -> Result<String, String>
+{
+    match (format, template) {
+        (DocFormat::Json, _) => to_json(docs),
+        (_, Some(path)) => crate::template::render_template(docs, format, path),
+        (DocFormat::Markdown, None) => Ok(to_markdown(docs)),
+        (DocFormat::Html, None) => crate::html::to_html(docs),
+    }
+}
+
+/// Turn text into a lowercase, dash separated anchor
+pub fn slug(text : &str) -> String
+{
+    text.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn module_anchor(prefix : &str, module : &DocModule) -> String
+{
+    format!("{prefix}module-{}", slug(&module.name))
+}
+
+pub fn item_anchor(prefix : &str, module : &DocModule, item : &DocItem) -> String
+{
+    format!("{prefix}{}-{}", slug(&module.name), slug(&item.title))
+}
+
+/// Anchor prefix for a package's docs. The root package has none, so its anchors stay stable with or without dependencies.
+pub fn package_prefix(docs : &DocSet, root : bool) -> String
+{
+    match (&docs.package, root) {
+        (Some(pack), false) => format!("{}-", slug(&pack.name)),
+        _ => String::new(),
+    }
+}
+
+/// The root package and each dependency, with their anchor prefixes
+pub fn packages(docs : &DocSet) -> Vec<(&DocSet, String)>
+{
+    std::iter::once((docs, package_prefix(docs, true)))
+        .chain(docs.dependencies.iter().map(|d| (d, package_prefix(d, false))))
+        .collect()
+}
+
+/// One entry in the search index of generated docs
+#[derive(serde::Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub signature: String,
+    pub description: String,
+    pub module: String,
+    pub package: Option<String>,
+    pub anchor: String,
+}
+
+/// Search index of every documented item, including dependencies
+pub fn search_index(docs : &DocSet) -> Vec<SearchEntry>
+{
+    let mut entries = Vec::new();
+    for (set, prefix) in packages(docs) {
+        for module in &set.modules {
+            for item in &module.items {
+                entries.push(SearchEntry {
+                    title: item.title.clone(),
+                    signature: item.signature.clone(),
+                    description: item.description.clone(),
+                    module: module.name.clone(),
+                    package: set.package.as_ref().map(|p| p.name.clone()),
+                    anchor: item_anchor(&prefix, module, item),
+                });
+            }
+        }
+    }
+    entries
+}
+
+/// Every documented item with its anchor, used to turn names in signatures into links
+pub struct LinkIndex {
+    /// (anchor prefix, module, title, anchor)
+    links: Vec<(String, String, String, String)>,
+}
+
+impl LinkIndex {
+    pub fn new(docs : &DocSet) -> Self
+    {
+        let mut links = Vec::new();
+        for (set, prefix) in packages(docs) {
+            for module in &set.modules {
+                for item in &module.items {
+                    links.push((prefix.clone(), module.name.clone(), item.title.clone(), item_anchor(&prefix, module, item)));
+                }
+            }
+        }
+        LinkIndex { links }
+    }
+
+    /// Find the anchor for a name used in `module`. Names may be qualified like `Module.name`.
+    /// Unqualified names prefer the same module, then the same package.
+    pub fn resolve(&self, name : &str, prefix : &str, module : &str) -> Option<&str>
+    {
+        if let Some((qualifier, title)) = name.rsplit_once('.') {
+            return self.links.iter()
+                .filter(|(_, m, t, _)| m == qualifier && t == title)
+                .min_by_key(|(p, _, _, _)| p != prefix)
+                .map(|(_, _, _, anchor)| anchor.as_str());
+        }
+        self.links.iter()
+            .filter(|(_, _, t, _)| t == name)
+            .min_by_key(|(p, m, _, _)| (p != prefix, m != module))
+            .map(|(_, _, _, anchor)| anchor.as_str())
+    }
+
+    /// Link every known name in a signature as markdown, except the item being documented
+    pub fn link_signature(&self, signature : &str, prefix : &str, module : &str, own_title : &str) -> String
+    {
+        self.link_with(signature, prefix, module, own_title, |text| text.to_string(), |name, anchor| format!("[{name}](#{anchor})"))
+    }
+
+    /// Link every known name in a signature, formatting plain text and links with the given functions
+    pub fn link_with(&self, signature : &str, prefix : &str, module : &str, own_title : &str,
+        text : impl Fn(&str) -> String, link : impl Fn(&str, &str) -> String) -> String
+    {
+        let mut content = String::new();
+        let mut last = 0;
+        for name in IDENTIFIER.find_iter(signature) {
+            content.push_str(&text(&signature[last..name.start()]));
+            match self.resolve(name.as_str(), prefix, module) {
+                Some(anchor) if name.as_str() != own_title => content.push_str(&link(name.as_str(), anchor)),
+                _ => content.push_str(&text(name.as_str())),
+            }
+            last = name.end();
+        }
+        content.push_str(&text(&signature[last..]));
+        content
+    }
+}
+
+/// Matches a possibly qualified name like `name` or `Module.name`
+static IDENTIFIER: LazyLock<Regex> = LazyLock::new(||
+    Regex::new(r"[A-Za-z_][A-Za-z0-9_']*(?:\.[A-Za-z_][A-Za-z0-9_']*)*").unwrap());
+
+pub fn to_markdown(docs : &DocSet) -> String
+{
+    let links = LinkIndex::new(docs);
+    let mut content = match &docs.package {
+        Some(pack) => format!("# {} {}\n\n", pack.name, pack.version),
+        None => String::from("# Documentation\n\n"),
+    };
+
+    // table of contents
+    content.push_str("## Contents\n");
+    content.push_str(&markdown_contents(docs, ""));
+    if !docs.dependencies.is_empty() {
+        content.push_str("\n### Dependencies\n");
+        for dep in &docs.dependencies {
+            let prefix = package_prefix(dep, false);
+            if let Some(pack) = &dep.package {
+                content.push_str(&format!("- [{} {}](#{prefix}package)\n", pack.name, pack.version));
+            }
+            for line in markdown_contents(dep, &prefix).lines() {
+                content.push_str(&format!("  {line}\n"));
+            }
+        }
+    }
+    content.push('\n');
+
+    content.push_str(&markdown_modules(docs, "", &links));
+    for dep in &docs.dependencies {
+        let prefix = package_prefix(dep, false);
+        if let Some(pack) = &dep.package {
+            content.push_str(&format!("<a id=\"{prefix}package\"></a>\n\n# Dependency {} {}\n\n", pack.name, pack.version));
+        }
+        content.push_str(&markdown_modules(dep, &prefix, &links));
+    }
+    content
+}
+
+/// Table of contents entries for the modules of one package
+fn markdown_contents(docs : &DocSet, prefix : &str) -> String
+{
+    let mut content = String::new();
+    for module in &docs.modules {
+        content.push_str(&format!("- [{}](#{})\n", module.name, module_anchor(prefix, module)));
+        for item in &module.items {
+            content.push_str(&format!("  - [{}](#{})\n", item.title, item_anchor(prefix, module, item)));
+        }
+    }
+    content
+}
+
+fn markdown_modules(docs : &DocSet, prefix : &str, links : &LinkIndex) -> String
+{
+    let mut content = String::new();
+    for module in &docs.modules {
+        content.push_str(&format!("<a id=\"{}\"></a>\n\n## Module `{}`\n", module_anchor(prefix, module), module.name));
+        content.push_str(&format!("*Source: {}*\n\n", module.file));
+        for item in &module.items {
+            let signature = links.link_signature(&item.signature, prefix, &module.name, &item.title);
+            content.push_str(&format!("<a id=\"{}\"></a>\n\n", item_anchor(prefix, module, item)));
+            content.push_str(&item_to_markdown(item, &signature));
+        }
+    }
+    content
+}
+
+fn item_to_markdown(item : &DocItem, signature : &str) -> String
+{
+    let mut content = format!("### {}: {}  \n", item.title, signature);
+
+    // badges
+    let mut badges = Vec::new();
+    if let Some(since) = &item.since {
+        badges.push(format!("`since {since}`"));
+    }
+    if item.deprecated.is_some() {
+        badges.push(String::from("`deprecated`"));
+    }
+    if !badges.is_empty() {
+        content.push_str(&format!("{}  \n", badges.join(" ")));
+    }
+    if let Some(notice) = item.deprecated.as_ref().filter(|n| !n.is_empty()) {
+        content.push_str(&format!("> **Deprecated:** {notice}\n\n"));
+    }
+
+    content.push_str(&format!("{}  \n", item.description));
+
+    if !item.params.is_empty() {
+        content.push_str("\n| Parameter | Description |\n| --- | --- |\n");
+        for param in &item.params {
+            content.push_str(&format!("| `{}` | {} |\n", param.name, table_cell(&param.description)));
+        }
+        content.push('\n');
+    }
+    if let Some(returns) = &item.returns {
+        content.push_str(&format!("**Returns:** {returns}  \n"));
+    }
+    if !item.see.is_empty() {
+        content.push_str(&format!("**See also:** {}  \n", item.see.join(", ")));
+    }
+    content.push_str("---\n");
+    content
+}
+
+/// Escape text so it fits in one markdown table cell
+fn table_cell(text : &str) -> String
+{
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+pub fn to_json(docs : &DocSet) -> Result<String, String>
+{
+    serde_json::to_string_pretty(docs).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(source : &str) -> DocSet
+    {
+        DocSet { package: None, modules: crate::parse::parse_source(source, "lib.hc", &mut Vec::new()), dependencies: Vec::new() }
+    }
+
+    const SHAPES : &str = "(* @title: shape\n   @signature: unit *)\n\
+        module Geo =\n  (* @title: shape\n     @signature: float * float *)\n  (* @title: area\n     @signature: shape -> float *)\nend\n\
+        (* @title: draw\n   @signature: Geo.shape -> area -> shape *)\n";
+
+    const AREA : &str = "(* @title: area\n   @signature: float -> float\n   @description: Area of a circle *)";
+
+    #[test]
+    fn json_keeps_the_whole_model()
+    {
+        let json : serde_json::Value = serde_json::from_str(&render(&docs(AREA), DocFormat::Json, None).unwrap()).unwrap();
+        assert_eq!(json["modules"][0]["items"][0]["signature"], "float -> float");
+        assert_eq!(json["modules"][0]["items"][0]["line"], 1);
+    }
+
+    #[test]
+    fn markdown_lists_every_item_under_its_module()
+    {
+        assert_eq!(render(&docs(AREA), DocFormat::Markdown, None).unwrap(), "# Documentation\n\n## Contents\n- [lib](#module-lib)\n  - [area](#lib-area)\n\n\
+            <a id=\"module-lib\"></a>\n\n## Module `lib`\n*Source: lib.hc*\n\n\
+            <a id=\"lib-area\"></a>\n\n### area: float -> float  \nArea of a circle  \n---\n");
+    }
+
+    #[test]
+    fn slugs_are_lowercase_and_dashed()
+    {
+        assert_eq!(slug("Geo.Shape  area_2"), "geo-shape-area-2");
+        assert_eq!(slug("--x--"), "x");
+    }
+
+    #[test]
+    fn markdown_table_cells_stay_on_one_row()
+    {
+        let markdown = to_markdown(&docs("(* @title: pick\n   @signature: int\n   @description: Picks\n   @param: x one | two\n     -- or three *)"));
+        assert!(markdown.contains("| `x` | one \\| two or three |"));
+    }
+
+    #[test]
+    fn names_resolve_to_the_nearest_item()
+    {
+        let links = LinkIndex::new(&docs(SHAPES));
+        assert_eq!(links.resolve("shape", "", "Geo"), Some("geo-shape"));
+        assert_eq!(links.resolve("shape", "", "lib"), Some("lib-shape"));
+        assert_eq!(links.resolve("Geo.shape", "", "lib"), Some("geo-shape"));
+        assert_eq!(links.resolve("area", "", "lib"), Some("geo-area"));
+        assert_eq!(links.resolve("missing", "", "lib"), None);
+    }
+
+    #[test]
+    fn signatures_link_every_known_name_but_their_own()
+    {
+        let links = LinkIndex::new(&docs(SHAPES));
+        assert_eq!(links.link_signature("Geo.shape -> area -> shape", "", "lib", "draw"),
+            "[Geo.shape](#geo-shape) -> [area](#geo-area) -> [shape](#lib-shape)");
+        assert_eq!(links.link_signature("shape -> float", "", "Geo", "shape"), "shape -> float");
+    }
+
+    #[test]
+    fn search_index_has_every_item_with_its_anchor()
+    {
+        let index = search_index(&docs(SHAPES));
+        let entries : Vec<(&str, &str, &str)> = index.iter().map(|e| (e.module.as_str(), e.title.as_str(), e.anchor.as_str())).collect();
+        assert_eq!(entries, vec![("lib", "shape", "lib-shape"), ("lib", "draw", "lib-draw"), ("Geo", "shape", "geo-shape"), ("Geo", "area", "geo-area")]);
+    }
+
+    #[test]
+    fn html_escapes_doc_text()
+    {
+        let html = render(&docs("(* @title: less\n   @signature: 'a -> <script>\n   @description: a < b && \"c\" </script>\n   @param: x <i>first</i> *)"),
+            DocFormat::Html, None).unwrap();
+        assert!(html.contains("<code>'a -&gt; &lt;script&gt;</code>"));
+        assert!(html.contains("a &lt; b &amp;&amp; &quot;c&quot; &lt;/script&gt;"));
+        assert!(html.contains("&lt;i&gt;first&lt;/i&gt;"));
+        // only the page's own script tag is closed
+        assert_eq!(html.matches("</script>").count(), 1);
+    }
+}
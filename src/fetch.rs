@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use colored::{ColoredString, Colorize};
+
+use crate::config::{create_config_from_path, Dependency};
+use crate::logging::*;
+use crate::pdm::{ensure_dep_cached, entry_to_dep, get_dep_cfg};
+
+/// Run `f` on every item with up to `jobs` threads, keeping the results in order
+fn parallel_map<T : Send, R : Send>(items : Vec<T>, jobs : usize, f : impl Fn(T) -> R + Sync) -> Vec<R>
+{
+    let count = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate().collect::<Vec<_>>());
+    let results = Mutex::new(Vec::with_capacity(count));
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, count.max(1)) {
+            scope.spawn(|| loop {
+                let Some((i, item)) = queue.lock().unwrap_or_else(|e| e.into_inner()).pop() else { break };
+                let result = f(item);
+                results.lock().unwrap_or_else(|e| e.into_inner()).push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Resolve one `[dependencies]` entry, make sure it is cached, and return it with its own dependency entries
+fn fetch_entry(name : &str, value : &toml::Value) -> Result<(Dependency, Vec<(String, toml::Value)>), ColoredString>
+{
+    let dep = entry_to_dep(name, value)?;
+    ensure_dep_cached(&dep)?;
+    let entries = get_dep_cfg(dep.clone())?.dependencies.unwrap_or_default().into_iter().collect();
+    Ok((dep, entries))
+}
+
+/// Resolve and download every transitive dependency into the cache, a level of the graph at a time
+pub fn fetch(jobs : Option<usize>) -> Result<(), ColoredString>
+{
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+    let timer = start_step("Fetching");
+
+    let mut fetched : Vec<Dependency> = Vec::new();
+    let mut seen_entries : Vec<(String, toml::Value)> = Vec::new();
+    let mut level : Vec<(String, toml::Value)> = cfg.dependencies.unwrap_or_default().into_iter().collect();
+    while !level.is_empty() {
+        // the same entry often appears in several packages
+        let mut unique = Vec::new();
+        for entry in std::mem::take(&mut level) {
+            if !seen_entries.contains(&entry) {
+                seen_entries.push(entry.clone());
+                unique.push(entry);
+            }
+        }
+        debug("Fetch", &format!("Fetching {} packages with {jobs} jobs", unique.len()));
+
+        let results = parallel_map(unique, jobs, |(name, value)| fetch_entry(&name, &value));
+        let mut failed = 0;
+        for result in results {
+            match result {
+                Ok((dep, entries)) => {
+                    if !fetched.iter().any(|d| d.name == dep.name && d.version == dep.version && d.source == dep.source) {
+                        fetched.push(dep);
+                        level.extend(entries);
+                    }
+                },
+                Err(e) => {
+                    error(&e);
+                    failed += 1;
+                },
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} {failed} dependencies could not be fetched", "Fetch error:".red()).into());
+        }
+    }
+
+    elapsed("Fetching", timer);
+    success(&format!("Fetched {} packages", fetched.len()));
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::doc::*;
+
+/// Styles for the generated page
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 0; display: flex; }
+nav { width: 18em; height: 100vh; overflow-y: auto; position: sticky; top: 0; padding: 1em; box-sizing: border-box; background: #f4f4f4; }
+nav ul { padding-left: 1em; }
+main { flex: 1; padding: 1em 2em; max-width: 60em; }
+code { background: #eee; padding: 0 .2em; }
+.item { border-bottom: 1px solid #ddd; padding-bottom: 1em; }
+.badge { display: inline-block; font-size: .8em; padding: .1em .5em; margin-right: .3em; border-radius: .3em; background: #def; }
+.badge.deprecated { background: #fdd; }
+#search { width: 100%; box-sizing: border-box; }
+#results li { margin: .3em 0; }
+#results .sig { display: block; font-size: .8em; color: #555; }
+"#;
+
+/// Offline search over the index embedded in the page
+const SEARCH_SCRIPT: &str = r##"
+const input = document.getElementById("search");
+const results = document.getElementById("results");
+input.addEventListener("input", () => {
+  const query = input.value.trim().toLowerCase();
+  results.innerHTML = "";
+  if (!query) return;
+  const matches = SEARCH_INDEX.filter(e =>
+    [e.title, e.signature, e.description, e.module].some(f => f.toLowerCase().includes(query)));
+  for (const e of matches.slice(0, 20)) {
+    const li = document.createElement("li");
+    const a = document.createElement("a");
+    a.href = "#" + e.anchor;
+    a.textContent = (e.package ? e.package + " / " : "") + e.module + "." + e.title;
+    const sig = document.createElement("span");
+    sig.className = "sig";
+    sig.textContent = e.signature;
+    li.append(a, sig);
+    results.append(li);
+  }
+});
+"##;
+
+/// Escape text for use in HTML
+pub fn escape(text : &str) -> String
+{
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render docs as a single HTML page with a search box
+pub fn to_html(docs : &DocSet) -> Result<String, String>
+{
+    let links = LinkIndex::new(docs);
+    let title = match &docs.package {
+        Some(pack) => format!("{} {}", pack.name, pack.version),
+        None => String::from("Documentation"),
+    };
+
+    // table of contents
+    let mut nav = String::from("<input id=\"search\" type=\"search\" placeholder=\"Search\">\n<ul id=\"results\"></ul>\n");
+    for (set, prefix) in packages(docs) {
+        if !prefix.is_empty() && let Some(pack) = &set.package {
+            nav.push_str(&format!("<h3><a href=\"#{prefix}package\">{}</a></h3>\n", escape(&pack.name)));
+        }
+        nav.push_str("<ul>\n");
+        for module in &set.modules {
+            nav.push_str(&format!("<li><a href=\"#{}\">{}</a><ul>\n", module_anchor(&prefix, module), escape(&module.name)));
+            for item in &module.items {
+                nav.push_str(&format!("<li><a href=\"#{}\">{}</a></li>\n", item_anchor(&prefix, module, item), escape(&item.title)));
+            }
+            nav.push_str("</ul></li>\n");
+        }
+        nav.push_str("</ul>\n");
+    }
+
+    let mut body = format!("<h1>{}</h1>\n", escape(&title));
+    for (set, prefix) in packages(docs) {
+        if !prefix.is_empty() && let Some(pack) = &set.package {
+            body.push_str(&format!("<h1 id=\"{prefix}package\">Dependency {} {}</h1>\n", escape(&pack.name), escape(&pack.version)));
+        }
+        for module in &set.modules {
+            body.push_str(&format!("<h2 id=\"{}\">Module <code>{}</code></h2>\n", module_anchor(&prefix, module), escape(&module.name)));
+            body.push_str(&format!("<p><em>Source: {}</em></p>\n", escape(&module.file)));
+            for item in &module.items {
+                let signature = links.link_with(&item.signature, &prefix, &module.name, &item.title,
+                    escape, |name, anchor| format!("<a href=\"#{anchor}\">{}</a>", escape(name)));
+                body.push_str(&item_to_html(item, &item_anchor(&prefix, module, item), &signature));
+            }
+        }
+    }
+
+    let index = serde_json::to_string(&search_index(docs)).map_err(|e| e.to_string())?
+        // keep the index from closing the script tag early
+        .replace("</", "<\\/");
+
+    Ok(format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<nav>\n{nav}</nav>\n<main>\n{body}</main>\n<script>\nconst SEARCH_INDEX = {index};\n{SEARCH_SCRIPT}</script>\n</body>\n</html>\n",
+        escape(&title)))
+}
+
+fn item_to_html(item : &DocItem, anchor : &str, signature : &str) -> String
+{
+    let mut content = format!("<div class=\"item\" id=\"{anchor}\">\n<h3>{}: <code>{signature}</code></h3>\n", escape(&item.title));
+
+    if let Some(since) = &item.since {
+        content.push_str(&format!("<span class=\"badge\">since {}</span>", escape(since)));
+    }
+    if item.deprecated.is_some() {
+        content.push_str("<span class=\"badge deprecated\">deprecated</span>");
+    }
+    if let Some(notice) = item.deprecated.as_ref().filter(|n| !n.is_empty()) {
+        content.push_str(&format!("<blockquote><strong>Deprecated:</strong> {}</blockquote>\n", escape(notice)));
+    }
+
+    content.push_str(&format!("<p>{}</p>\n", escape(&item.description).replace('\n', "<br>\n")));
+
+    if !item.params.is_empty() {
+        content.push_str("<table>\n<tr><th>Parameter</th><th>Description</th></tr>\n");
+        for param in &item.params {
+            content.push_str(&format!("<tr><td><code>{}</code></td><td>{}</td></tr>\n", escape(&param.name), escape(&param.description)));
+        }
+        content.push_str("</table>\n");
+    }
+    if let Some(returns) = &item.returns {
+        content.push_str(&format!("<p><strong>Returns:</strong> {}</p>\n", escape(returns)));
+    }
+    if !item.see.is_empty() {
+        let see : Vec<_> = item.see.iter().map(|s| escape(s)).collect();
+        content.push_str(&format!("<p><strong>See also:</strong> {}</p>\n", see.join(", ")));
+    }
+    content.push_str("</div>\n");
+    content
+}
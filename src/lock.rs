@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use colored::{ColoredString, Colorize};
+
+use crate::logging::*;
+
+/// Lockfile struct, records the exact version each dependency resolved to
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Clone, PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    /// Archive checksum of a registry package
+    pub checksum: Option<String>,
+    /// Commit a git package was cloned at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// sha256 of the unpacked files, see `cache::tree_checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    /// Public key whose signature was checked when the package was resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_by: Option<String>,
+    /// Deprecation message of the package, so builds that don't read the index still warn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+}
+
+/// Serializes updates to Gup.lock between threads
+static WRITE : Mutex<()> = Mutex::new(());
+
+/// Record a package in the project's Gup.lock, and register the project with the cache
+pub fn record(package : LockedPackage) -> Result<(), ColoredString>
+{
+    // reload under the mutex, another thread may have locked other packages meanwhile
+    let _guard = WRITE.lock().unwrap_or_else(|e| e.into_inner());
+    let path = PathBuf::from(crate::LOCK_PATH);
+    let mut lock = Lockfile::load(&path)?;
+    lock.insert(package);
+    lock.save(&path)?;
+    crate::cache::register_project()
+}
+
+impl Lockfile {
+    /// Read a lockfile, or an empty one if it does not exist
+    pub fn load(path : &Path) -> Result<Lockfile, ColoredString>
+    {
+        if !path.exists() {
+            return Ok(Lockfile::default());
+        }
+        debug("Lockfile::load", &format!("Reading lockfile \"{}\"", path.to_string_lossy()));
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("{} {}", "Lockfile error:\n".red(), e))?;
+        toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read lockfile".red()).into())
+    }
+
+    pub fn save(&self, path : &Path) -> Result<(), ColoredString>
+    {
+        let contents = toml::to_string(self)
+            .map_err(|e| e.to_string())?;
+        std::fs::write(path, format!("# This file is generated by gup. Do not edit it by hand.\n{contents}"))
+            .map_err(|e| e.to_string().red())?;
+        Ok(())
+    }
+
+    /// Newest locked version of a package from a source that matches a requirement.
+    /// Several versions of one package can be locked when dependencies need incompatible ones.
+    pub fn find(&self, name : &str, source : &str, requirement : &semver::VersionReq) -> Option<&LockedPackage>
+    {
+        self.packages.iter()
+            .filter(|p| p.name == name && p.source == source)
+            .filter_map(|p| semver::Version::parse(&p.version).ok().filter(|v| requirement.matches(v)).map(|v| (v, p)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, p)| p)
+    }
+
+    /// Locked entry for an exact version of a package from a source
+    pub fn find_exact(&self, name : &str, version : &str, source : &str) -> Option<&LockedPackage>
+    {
+        self.packages.iter().find(|p| p.name == name && p.version == version && p.source == source)
+    }
+
+    /// Record a package, replacing the entry for the same version from the same source
+    pub fn insert(&mut self, package : LockedPackage)
+    {
+        self.packages.retain(|p| !(p.name == package.name && p.version == package.version && p.source == package.source));
+        self.packages.push(package);
+        self.packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    }
+}
@@ -14,10 +14,27 @@ use logging::*;
 mod build;
 use build::*;
 mod parse;
+mod doc;
+mod coverage;
+mod html;
+mod serve;
+mod template;
+mod compat;
+mod lock;
+mod registry;
+mod publish;
+mod cache;
+mod vendor;
+mod fetch;
+mod verify;
+mod sign;
+mod audit;
+mod search;
 
 const CONFIG_PATH : &str = "./Config.toml";
 const OUTPUT_PATH : &str = "./a.wasm";
 const DOCS_PATH : &str = "./docs.md";
+const LOCK_PATH : &str = "./Gup.lock";
 
 
 fn gup_main() -> Result<(), ColoredString> {
@@ -25,10 +42,21 @@ fn gup_main() -> Result<(), ColoredString> {
     env_logger::Builder::new()
         .filter_module("gup",args.verbose.log_level_filter())
         .init();
+    if let Some(cache_dir) = args.cache_dir {
+        set_cache_dir(cache_dir);
+    }
+    set_offline(args.offline || args.frozen);
+    set_locked(args.locked || args.frozen);
     match args.command {
         Commands::Check => {
             let cfg = create_config_from_path(&PathBuf::from(CONFIG_PATH))?;
             check_valid(&cfg)?;
+            // in CI, also check that the dependencies resolve from Gup.lock and the cache alone
+            if is_locked() || is_offline() {
+                for (name, value) in cfg.dependencies.iter().flatten() {
+                    ensure_dep_cached(&entry_to_dep(name, value)?)?;
+                }
+            }
         },
         Commands::Build => {
             let cfg = create_config_from_path(&PathBuf::from(CONFIG_PATH))?;
@@ -76,6 +104,7 @@ fn gup_main() -> Result<(), ColoredString> {
                 Package {
                 name: proj_name.trim().into(),
                 version: "0.1.0".into(),
+                description: None,
             });
             
             // write each infile as a .hc module
@@ -83,7 +112,7 @@ fn gup_main() -> Result<(), ColoredString> {
                 let path = std::path::PathBuf::from(&arg);
                 let module_name = match path.file_stem().and_then(|s| s.to_str()) {
                     Some(name) => name,
-                    None => return Err("Invalid or missing filename for module".red().into()),
+                    None => return Err("Invalid or missing filename for module".red()),
                 };
                 let content = format!("module {} =\n(* Your code here! *)\nend", module_name);
                 std::fs::write(path, content)
@@ -104,42 +133,61 @@ fn gup_main() -> Result<(), ColoredString> {
             }
             success("Successfully initialized halcyon project");
         },
-        Commands::Doc => {
+        Commands::Doc(doc_group) => {
             let cfg = create_config_from_path(&PathBuf::from(CONFIG_PATH))?;
-            parse::create_docs(cfg)?;
+            if doc_group.coverage || doc_group.deny_missing {
+                let mut warnings = Vec::new();
+                let modules = coverage::coverage(&cfg, &mut warnings)?;
+                for warning in &warnings {
+                    warn("Doc", &warning.to_string());
+                }
+                coverage::print_coverage(&modules);
+
+                let threshold = cfg.doc.as_ref().and_then(|d| d.coverage_threshold).unwrap_or(100.0);
+                let total = coverage::total_percent(&modules);
+                if doc_group.deny_missing && total < threshold {
+                    return Err(format!("{} {:.1}% is below the threshold of {:.1}%", "Doc coverage:".red(), total, threshold).into());
+                }
+                return Ok(());
+            }
+            if doc_group.serve {
+                return serve::serve_docs(doc_group.port, doc_group.with_deps);
+            }
+            parse::create_docs(cfg, doc_group.format, doc_group.with_deps)?;
             success("Docs created");
         },
         Commands::Add(add_group) => {
-            match (add_group.path, add_group.url){
-                (Some(path), None) =>{
-                    let cfg = create_config_from_path(&PathBuf::from(path.clone()).join(CONFIG_PATH))?;
-                    let pack = cfg.package.expect("Dpendency has invalid config!");
-                    let package_name = format!("{}-{}", pack.name.chars().filter(|c| !c.is_whitespace()).collect::<String>(), pack.version );
-                    let new_dir_name : PathBuf = get_hc_filepath()?.join::<PathBuf>(package_name.into());
-                    if std::fs::exists(&new_dir_name).map_err(|e|e.to_string())?
-                    {
-                        std::fs::remove_dir_all(&new_dir_name).map_err(|e|e.to_string())?;
-                    }
-                    
-                    copy_dir::copy_dir(&path, new_dir_name)
-                        .map_err(|e|e.to_string())?;
-
-                    
-                    let new_dep = Dependency {
-                        name: pack.name.chars().filter(|c| !c.is_whitespace()).collect(),
-                        source: path.clone(),
-                        version: pack.version,
-                    };
+            match (add_group.path, add_group.url, add_group.package){
+                (Some(path), None, None) =>{
+                    let new_dep = add_path_dependency(&path)?;
                     add_dep_to_config(new_dep, CONFIG_PATH)?;   
                     success(&format!("Successfully added {} as a dependency.", &path));
                 },
-                (None,Some(url))=> {
+                (None,Some(url), None)=> {
                     let new_package = add_dependency(url.clone())?;
                     add_dep_to_config(new_package, CONFIG_PATH)?;            
                     success(&format!("Successfully added {} as a dependency.", &url));
                 },
+                (None, None, Some(package)) => {
+                    let registry = add_group.registry.as_deref();
+                    let dep = match package.split_once('@') {
+                        Some((name, requirement)) => {
+                            let dep = registry::resolve_registry_dep(name, requirement, registry)?;
+                            add_registry_dep_to_config(name, requirement, registry, CONFIG_PATH)?;
+                            dep
+                        },
+                        None => {
+                            // default to versions compatible with the newest one
+                            let dep = registry::resolve_registry_dep(&package, "*", registry)?;
+                            add_registry_dep_to_config(&package, &format!("^{}", dep.version), registry, CONFIG_PATH)?;
+                            dep
+                        }
+                    };
+                    success(&format!("Successfully added {} {} as a dependency.", dep.name, dep.version));
+                },
                 _ => unreachable!()
             }
+            cache::register_project()?;
 
             if add_group.tree {
                 print_dep_tree()?
@@ -154,6 +202,53 @@ fn gup_main() -> Result<(), ColoredString> {
         },
         Commands::Tree => {
             print_dep_tree()?
+        },
+        Commands::SemverCheck(semver_group) => {
+            compat::semver_check(semver_group.baseline)?;
+        },
+        Commands::Publish(publish_group) => {
+            publish::publish(publish_group.registry.as_deref(), publish_group.dry_run, !publish_group.no_verify, publish_group.sign)?;
+        },
+        Commands::Login(login_group) => {
+            registry::login(login_group.registry.as_deref(), login_group.token)?;
+        },
+        Commands::Search(search_group) => {
+            search::search(&search_group.query, search_group.registry.as_deref())?;
+        },
+        Commands::Info(info_group) => {
+            search::package_info(&info_group.name, info_group.registry.as_deref())?;
+        },
+        Commands::Fetch(fetch_group) => {
+            fetch::fetch(fetch_group.jobs)?;
+        },
+        Commands::Key(key_group) => {
+            match key_group.command {
+                KeyCommands::Generate(generate_group) => sign::generate(generate_group.force)?,
+                KeyCommands::Show => sign::show()?,
+            }
+        },
+        Commands::Verify => {
+            verify::verify()?;
+        },
+        Commands::Audit(audit_group) => {
+            audit::audit(audit_group.db.as_deref())?;
+        },
+        Commands::Vendor(vendor_group) => {
+            vendor::vendor(&vendor_group.dir)?;
+        },
+        Commands::Cache(cache_group) => {
+            match cache_group.command {
+                CacheCommands::List => cache::list()?,
+                CacheCommands::Size => cache::size()?,
+                CacheCommands::Clean(clean_group) => cache::clean(clean_group.name.as_deref())?,
+                CacheCommands::Gc(gc_group) => cache::gc(gc_group.older_than)?,
+            }
+        },
+        Commands::Yank(yank_group) => {
+            publish::yank(&yank_group.package, yank_group.registry.as_deref(), yank_group.undo)?;
+        },
+        Commands::Deprecate(deprecate_group) => {
+            publish::deprecate(&deprecate_group.name, deprecate_group.message.as_deref(), deprecate_group.registry.as_deref())?;
         }
     }
     Ok(())
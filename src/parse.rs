@@ -1,42 +1,526 @@
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
 use regex::Regex;
 use crate::config::Config;
+use crate::doc::{DocFormat, DocItem, DocModule, DocPackage, DocParam, DocSet};
+use crate::logging::*;
+use crate::pdm::{get_dep_cfg, get_dep_dir, get_dep_list};
+
+/// Matches an annotation like `@title:`
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"@([A-Za-z_]+):").unwrap());
+/// Matches a line that starts with an annotation missing its colon, like `@param x first`
+static MALFORMED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^[ \t]*(?:--[ \t]*)?@([A-Za-z_]+)(?:[^:A-Za-z_]|$)").unwrap());
+/// Matches an `@` standing on its own, which ends a multi-line annotation
+static TERMINATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|\s)@(?:\s|$)").unwrap());
+/// Matches a module declaration like `module main =`, and the keywords that open or close a block ending in `end`
+static BLOCK: LazyLock<Regex> = LazyLock::new(||
+    Regex::new(r"\b(?:module\s+(?P<module>[A-Za-z_][A-Za-z0-9_]*)\s*=(?:\s*(?:struct|sig)\b)?|(?:struct|sig|begin|object|end)\b)").unwrap());
+
+/// Problem found while scanning doc comments
+pub struct DocWarning {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for DocWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// A `(* ... *)` comment with the line it starts on
+pub struct Comment<'a> {
+    pub line: usize,
+    pub body: &'a str,
+    /// Byte range of the whole comment, including delimiters
+    pub span: std::ops::Range<usize>,
+}
 
-pub fn create_docs(cfg : Config) -> Result<(),String>
+/// Find every top-level comment in a Halcyon source file.
+/// Comments nest, and comment markers inside string literals are ignored.
+pub fn scan_comments<'a>(source : &'a str, file : &str, warnings : &mut Vec<DocWarning>) -> Vec<Comment<'a>>
 {
-    let mut content = String::new();
-    for path in cfg.build.infiles{
-        let source = std::fs::read_to_string(std::path::PathBuf::from(&path))
-        .unwrap_or_else(|e| panic!("Failed to read {path:?}: {e}"));
-        
-        let title_matches = Regex::new(r"@title:\s*([^\r\n]*)").unwrap();
-        let signature_matches = Regex::new(r"@signature:\s*([^\r\n]*)").unwrap();
-        let description_matches = Regex::new(r"@description:\s*([^@]*)").unwrap();
+    let bytes = source.as_bytes();
+    let mut comments = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
 
-        let titles: Vec<_> = title_matches.captures_iter(&source)
-            .filter_map(|c| c.get(1).map(|m| m.as_str().trim().to_string()))
-            .collect();
+    // (start of body, start line)
+    let mut open : Option<(usize, usize)> = None;
+    let mut depth = 0;
+    let mut in_string = false;
 
-        let signatures: Vec<_> = signature_matches.captures_iter(&source)
-            .filter_map(|c| c.get(1).map(|m| m.as_str().trim().to_string()))
-            .collect();
+    while i < bytes.len() {
+        let next = bytes.get(i + 1).copied();
+        match (bytes[i], next) {
+            (b'\n', _) => line += 1,
+            (b'\\', _) if in_string => {
+                if next == Some(b'\n') { line += 1; }
+                i += 1;
+            },
+            (b'"', _) if depth == 0 => in_string = !in_string,
+            (b'(', Some(b'*')) if !in_string => {
+                if depth == 0 {
+                    open = Some((i + 2, line));
+                }
+                depth += 1;
+                i += 1;
+            },
+            (b'*', Some(b')')) if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let (start, start_line) = open.take().expect("open comment should have a start");
+                    comments.push(Comment { line: start_line, body: &source[start..i], span: start - 2..i + 2 });
+                }
+                i += 1;
+            },
+            _ => {}
+        }
+        i += 1;
+    }
 
-        let descriptions: Vec<_> = description_matches.captures_iter(&source)
-            .filter_map(|c| c.get(1).map(|m| m.as_str().trim().to_string()))
-            .map(|d| d.replace("--", "")) // strip "--"
-            .collect();
+    if let Some((_, start_line)) = open {
+        warnings.push(DocWarning {
+            file: file.into(),
+            line: start_line,
+            message: "Unterminated comment, ignoring it".into(),
+        });
+    }
+    comments
+}
+
+/// Parse the annotations in one comment block. Returns `None` if the comment is not a doc block.
+pub fn parse_doc_block(comment : &Comment, file : &str, warnings : &mut Vec<DocWarning>) -> Option<DocItem>
+{
+    let tags : Vec<_> = TAG.captures_iter(comment.body).collect();
+    if tags.is_empty() {
+        return None;
+    }
+
+    let mut warn_at = |offset : usize, message : String| warnings.push(DocWarning {
+        file: file.into(),
+        line: comment.line + comment.body[..offset].matches('\n').count(),
+        message,
+    });
+    for malformed in MALFORMED.captures_iter(comment.body) {
+        let name = &malformed[1];
+        warn_at(malformed.get(0).unwrap().start(), format!("Malformed annotation @{name}, expected @{name}:"));
+    }
+
+    // (offset of the tag, text) for each single-valued annotation
+    let mut title : Option<(usize, String)> = None;
+    let mut signature : Option<(usize, String)> = None;
+    let mut description : Option<(usize, String)> = None;
+    let mut returns : Option<(usize, String)> = None;
+    let mut since : Option<(usize, String)> = None;
+    let mut deprecated : Option<(usize, String)> = None;
+    let mut params = Vec::new();
+    let mut see = Vec::new();
+
+    for (i, tag) in tags.iter().enumerate() {
+        let whole = tag.get(0).unwrap();
+        let name = &tag[1];
+        let value_end = tags.get(i + 1).map(|t| t.get(0).unwrap().start()).unwrap_or(comment.body.len());
+        let value = &comment.body[whole.end()..value_end];
+
+        let (slot, text) = match name {
+            "title" => (&mut title, first_line(value)),
+            "signature" => (&mut signature, first_line(value)),
+            "since" => (&mut since, first_line(value)),
+            "description" => (&mut description, block_text(value)),
+            "returns" => (&mut returns, block_text(value)),
+            "deprecated" => (&mut deprecated, block_text(value)),
+            "param" => {
+                let text = block_text(value);
+                let (param, desc) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+                if param.is_empty() {
+                    warn_at(whole.start(), "@param has no parameter name, ignoring it".into());
+                } else {
+                    params.push(DocParam { name: param.into(), description: desc.trim().into() });
+                }
+                continue;
+            },
+            "see" => {
+                let text = first_line(value);
+                if text.is_empty() {
+                    warn_at(whole.start(), "Empty @see, ignoring it".into());
+                } else {
+                    see.push(text);
+                }
+                continue;
+            },
+            _ => {
+                warn_at(whole.start(), format!("Unknown annotation @{name}, ignoring it"));
+                continue;
+            }
+        };
+
+        if slot.is_some() {
+            warn_at(whole.start(), format!("Duplicate @{name}, ignoring it"));
+        } else {
+            *slot = Some((whole.start(), text));
+        }
+    }
+
+    let (title_offset, title) = match title {
+        Some((offset, title)) if !title.is_empty() => (offset, title),
+        _ => {
+            warn_at(0, "Doc block has no @title, skipping it".into());
+            return None;
+        }
+    };
+    let line = comment.line + comment.body[..title_offset].matches('\n').count();
+
+    let signature = signature.map(|(_, s)| s).filter(|s| !s.is_empty()).unwrap_or_else(|| {
+        warnings.push(DocWarning { file: file.into(), line, message: format!("\"{title}\" has no @signature") });
+        String::from("No signature provided")
+    });
+    let description = description.map(|(_, d)| d).filter(|d| !d.is_empty()).unwrap_or_else(|| {
+        warnings.push(DocWarning { file: file.into(), line, message: format!("\"{title}\" has no @description") });
+        String::from("No description provided")
+    });
+    debug("parse_doc_block", &format!("Found \"{title}\" at {file}:{line}"));
+
+    Some(DocItem {
+        title,
+        signature,
+        description,
+        params,
+        returns: returns.map(|(_, r)| r).filter(|r| !r.is_empty()),
+        since: since.map(|(_, s)| s).filter(|s| !s.is_empty()),
+        deprecated: deprecated.map(|(_, d)| d),
+        see,
+        file: file.into(),
+        line,
+    })
+}
+
+/// Text of a single line annotation
+fn first_line(value : &str) -> String
+{
+    value.lines().next().unwrap_or("").trim().to_string()
+}
+
+/// Text of a multi-line annotation, which ends at a lone `@`, a malformed annotation line or the end of the block.
+/// An `@` inside a word, like in `a@b.com`, is part of the text.
+fn block_text(value : &str) -> String
+{
+    let end = [TERMINATOR.find(value), MALFORMED.find(value)].into_iter()
+        .flatten()
+        .map(|m| m.start())
+        .min()
+        .unwrap_or(value.len());
+    clean_description(&value[..end])
+}
+
+/// Trim each line of a description and strip leading "--" markers
+fn clean_description(text : &str) -> String
+{
+    text.lines()
+        .map(|l| l.trim())
+        .map(|l| l.strip_prefix("--").unwrap_or(l).trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Module declarations in a source file, used to find which module an offset belongs to
+pub struct ModuleMap {
+    /// (range from the declaration to its `end`, name) of each module
+    modules: Vec<(std::ops::Range<usize>, String)>,
+    file_stem: String,
+}
+
+impl ModuleMap {
+    pub fn new(source : &str, file : &str, comments : &[Comment]) -> Self
+    {
+        let strings = scan_strings(source, comments);
+        let mut modules = Vec::new();
+        // one entry per open block, with the start and name of the ones that are modules
+        let mut open : Vec<Option<(usize, String)>> = Vec::new();
+        for block in BLOCK.captures_iter(source) {
+            let whole = block.get(0).unwrap();
+            if in_comment(comments, whole.start()) || strings.iter().any(|s| s.contains(&whole.start())) {
+                continue;
+            }
+            if let Some(name) = block.name("module") {
+                open.push(Some((whole.start(), name.as_str().into())));
+            } else if whole.as_str() == "end" {
+                if let Some(Some((start, name))) = open.pop() {
+                    modules.push((start..whole.end(), name));
+                }
+            } else {
+                open.push(None);
+            }
+        }
+        // a module missing its `end` runs to the end of the file
+        modules.extend(open.into_iter().flatten().map(|(start, name)| (start..source.len(), name)));
+
+        let file_stem = Path::new(file).file_stem().map(|s| s.to_string_lossy().into()).unwrap_or(file.to_string());
+        ModuleMap { modules, file_stem }
+    }
+
+    /// Name of the innermost module around an offset, or the file name outside of any module
+    pub fn name_at(&self, offset : usize) -> String
+    {
+        self.modules.iter()
+            .filter(|(range, _)| range.contains(&offset))
+            .max_by_key(|(range, _)| range.start)
+            .map(|(_, name)| name.clone())
+            .unwrap_or(self.file_stem.clone())
+    }
+}
+
+/// Byte ranges of the string literals outside of comments
+pub fn scan_strings(source : &str, comments : &[Comment]) -> Vec<std::ops::Range<usize>>
+{
+    let bytes = source.as_bytes();
+    let mut strings = Vec::new();
+    let mut open = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], open) {
+            (b'\\', Some(_)) => i += 1,
+            (b'"', Some(start)) => {
+                strings.push(start..i + 1);
+                open = None;
+            },
+            (b'"', None) if !in_comment(comments, i) => open = Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    if let Some(start) = open {
+        strings.push(start..source.len());
+    }
+    strings
+}
+
+/// Whether a byte offset is inside one of the given comments
+pub fn in_comment(comments : &[Comment], offset : usize) -> bool
+{
+    comments.iter().any(|c| c.span.contains(&offset))
+}
 
-        // Use the longest length
-        let max_len = titles.len().max(signatures.len()).max(descriptions.len());
+/// Extract the documented items from one source file, grouped by the module they are declared in.
+/// Items outside of any module are grouped under the file name.
+pub fn parse_source(source : &str, file : &str, warnings : &mut Vec<DocWarning>) -> Vec<DocModule>
+{
+    let comments = scan_comments(source, file, warnings);
+    let modules_at = ModuleMap::new(source, file, &comments);
+
+    let mut modules : Vec<DocModule> = Vec::new();
+    for comment in &comments {
+        let Some(item) = parse_doc_block(comment, file, warnings) else { continue };
+        let name = modules_at.name_at(comment.span.start);
 
-        
-        for i in 0..max_len {
-            let title = titles.get(i).cloned().unwrap_or(String::from("No title provided"));
-            let signature = signatures.get(i).cloned().unwrap_or(String::from("No signature provided"));
-            let description = descriptions.get(i).cloned().unwrap_or(String::from("No description provided"));
-            content.push_str(&format!("## {}: {}  \n{}  \n---\n", title, signature, description));
+        match modules.iter_mut().find(|m| m.name == name) {
+            Some(module) => module.items.push(item),
+            None => modules.push(DocModule { name, file: file.into(), items: vec![item] }),
         }
     }
-    
-    std::fs::write(cfg.build.docfile.unwrap_or(String::from("./docs.md")), content).unwrap();
+    modules
+}
+
+/// Extract the documentation for every infile in a config. Infiles are relative to `base`.
+pub fn extract_docs(cfg : &Config, base : &Path, warnings : &mut Vec<DocWarning>) -> Result<DocSet, String>
+{
+    let mut modules = Vec::new();
+    for path in &cfg.build.infiles{
+        let source = std::fs::read_to_string(base.join(path))
+            .map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+
+        modules.extend(parse_source(&source, path, warnings));
+    }
+
+    Ok(DocSet {
+        package: cfg.package.as_ref().map(DocPackage::from),
+        modules,
+        dependencies: Vec::new(),
+    })
+}
+
+/// Extract the documentation for every package in the dependency tree of a config
+pub fn extract_dep_docs(cfg : &Config, warnings : &mut Vec<DocWarning>) -> Result<Vec<DocSet>, String>
+{
+    let mut docs = Vec::new();
+    for dep in get_dep_list(cfg).map_err(|e| e.to_string())? {
+        debug("extract_dep_docs", &format!("Extracting docs for {}", dep.name));
+        let dir = get_dep_dir(&dep).map_err(|e| e.to_string())?;
+        let dep_cfg = get_dep_cfg(dep).map_err(|e| e.to_string())?;
+        docs.push(extract_docs(&dep_cfg, &dir, warnings)?);
+    }
+    Ok(docs)
+}
+
+/// Path docs are written to for a given format. Markdown goes to `docfile` as written,
+/// other formats next to it with their own extension.
+pub fn doc_output_path(cfg : &Config, format : DocFormat) -> PathBuf
+{
+    let docfile = PathBuf::from(cfg.build.docfile.clone().unwrap_or(String::from(crate::DOCS_PATH)));
+    match format {
+        DocFormat::Markdown => docfile,
+        _ => docfile.with_extension(format.extension()),
+    }
+}
+
+pub fn create_docs(cfg : Config, format : DocFormat, with_deps : bool) -> Result<(),String>
+{
+    let mut warnings = Vec::new();
+    let mut docs = extract_docs(&cfg, Path::new("."), &mut warnings)?;
+    if with_deps {
+        docs.dependencies = extract_dep_docs(&cfg, &mut warnings)?;
+    }
+    for warning in &warnings {
+        warn("Doc", &warning.to_string());
+    }
+    let template = cfg.doc.as_ref().and_then(|d| d.template.as_ref()).map(PathBuf::from);
+    let content = crate::doc::render(&docs, format, template.as_deref())?;
+
+    let out_path = doc_output_path(&cfg, format);
+    std::fs::write(&out_path, content)
+        .map_err(|e| format!("Failed to write {}: {e}", out_path.to_string_lossy()))?;
+
+    if format == DocFormat::Html {
+        // the page embeds its own copy, this one is for other tools
+        let index_path = out_path.with_extension("search.json");
+        let index = serde_json::to_string_pretty(&crate::doc::search_index(&docs)).map_err(|e| e.to_string())?;
+        std::fs::write(&index_path, index)
+            .map_err(|e| format!("Failed to write {}: {e}", index_path.to_string_lossy()))?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source : &str) -> (Vec<DocModule>, Vec<DocWarning>)
+    {
+        let mut warnings = Vec::new();
+        let modules = parse_source(source, "lib.hc", &mut warnings);
+        (modules, warnings)
+    }
+
+    fn config(docfile : &str) -> Config
+    {
+        toml::from_str(&format!("[build]\ninfiles = [\"./main.hc\"]\noutfile = \"./a.wasm\"\ndocfile = \"{docfile}\"\n")).unwrap()
+    }
+
+    #[test]
+    fn json_docs_are_written_next_to_the_docfile()
+    {
+        assert_eq!(doc_output_path(&config("./api/docs.md"), DocFormat::Json), PathBuf::from("./api/docs.json"));
+        assert_eq!(doc_output_path(&config("./api/docs.md"), DocFormat::Markdown), PathBuf::from("./api/docs.md"));
+        assert_eq!(doc_output_path(&config("./api.txt"), DocFormat::Markdown), PathBuf::from("./api.txt"));
+        assert_eq!(doc_output_path(&config("./api.txt"), DocFormat::Html), PathBuf::from("./api.html"));
+    }
+
+    #[test]
+    fn comments_nest_and_ignore_strings()
+    {
+        let mut warnings = Vec::new();
+        let comments = scan_comments("let s = \"(* not a comment\"\n(* outer (* inner *) still outer *)\n(* open", "lib.hc", &mut warnings);
+        assert_eq!(comments.len(), 1);
+        assert_eq!((comments[0].line, comments[0].body), (2, " outer (* inner *) still outer "));
+        assert_eq!((warnings[0].line, warnings[0].message.as_str()), (3, "Unterminated comment, ignoring it"));
+    }
+
+    #[test]
+    fn titles_keep_their_own_signatures()
+    {
+        let (modules, warnings) = parse("(* @title: first\n   @description: No signature *)\nlet first = 1\n\n\
+            (* @title: second\n   @signature: int -> int\n   @description: Doubles *)\nlet second x = x\n");
+        let items = &modules[0].items;
+        assert_eq!((items[0].title.as_str(), items[0].signature.as_str()), ("first", "No signature provided"));
+        assert_eq!((items[1].title.as_str(), items[1].signature.as_str()), ("second", "int -> int"));
+        assert_eq!(items[1].line, 5);
+        assert!(warnings.iter().any(|w| w.line == 1 && w.message == "\"first\" has no @signature"));
+    }
+
+    #[test]
+    fn description_keeps_at_inside_words()
+    {
+        let (modules, _) = parse("(* @title: help\n   @signature: unit\n   @description: Mail a@b.com for help\n   -- or ask @maintainers\n   @\n   not part of it *)");
+        assert_eq!(modules[0].items[0].description, "Mail a@b.com for help\nor ask @maintainers");
+    }
+
+    #[test]
+    fn malformed_annotation_is_reported_not_swallowed()
+    {
+        let (modules, warnings) = parse("(* @title: add\n   @signature: int -> int -> int\n   @description: Adds two numbers\n   @param x first\n   @returns: The sum *)");
+        let item = &modules[0].items[0];
+        assert_eq!(item.description, "Adds two numbers");
+        assert!(item.params.is_empty());
+        assert_eq!(item.returns.as_deref(), Some("The sum"));
+        assert!(warnings.iter().any(|w| w.line == 4 && w.message == "Malformed annotation @param, expected @param:"));
+    }
+
+    #[test]
+    fn each_comment_is_its_own_block()
+    {
+        let (modules, _) = parse("(* @title: first\n   @signature: int\n   @description: First, with no terminator *)\nlet first = 1\n\
+            (* @title: second\n   @signature: int\n   @description: Second *)\nlet second = 2\n");
+        let items = &modules[0].items;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].description, "First, with no terminator");
+        assert_eq!(items[1].description, "Second");
+    }
+
+    #[test]
+    fn odd_input_does_not_panic()
+    {
+        for source in ["", "(*", "*)", "(*)", "(* @", "(* @title: *)", "(* @title:", "(* @: x *)", "(* @param: *)",
+            "\"(* not a comment\" (* @title: x *)", "(* (* nested @title: y *) *)", "(* @title: é\n@description: ü@ *)",
+            "module", "module M =", "end end", "(* @title: x\n@description: \\ *)\"\\"] {
+            parse(source);
+        }
+    }
+
+    #[test]
+    fn annotations_fill_the_item()
+    {
+        let (modules, warnings) = parse("(* @title: add\n   @signature: int -> int -> int\n   @description: Adds\n\
+            @param: x first\n     -- number\n   @param: y second\n   @returns: The sum\n   @since: 0.2.0\n\
+            @deprecated: Use plus\n   @see: plus\n   @see: sub *)");
+        let items = &modules[0].items;
+        let item = &items[0];
+        let params : Vec<_> = item.params.iter().map(|p| (p.name.as_str(), p.description.as_str())).collect();
+        assert_eq!(params, vec![("x", "first\nnumber"), ("y", "second")]);
+        assert_eq!(item.returns.as_deref(), Some("The sum"));
+        assert_eq!(item.since.as_deref(), Some("0.2.0"));
+        assert_eq!(item.deprecated.as_deref(), Some("Use plus"));
+        assert_eq!(item.see, vec!["plus", "sub"]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn duplicate_and_unknown_annotations_warn()
+    {
+        let (modules, warnings) = parse("(* @title: f\n   @signature: int\n   @description: F\n   @returns: one\n   @returns: two\n   @throws: never *)");
+        let items = &modules[0].items;
+        assert_eq!(items[0].returns.as_deref(), Some("one"));
+        let messages : Vec<_> = warnings.iter().map(|w| (w.line, w.message.as_str())).collect();
+        assert_eq!(messages, vec![(5, "Duplicate @returns, ignoring it"), (6, "Unknown annotation @throws, ignoring it")]);
+    }
+
+    #[test]
+    fn items_are_grouped_by_module_range()
+    {
+        let (modules, _) = parse("(* @title: top *)\nlet top = 1\n\
+            module Outer =\n  (* @title: outer *)\n  let s = begin \"end\" end\n  (* end *)\n\
+              module Inner = struct\n    (* @title: inner *)\n  end\n  (* @title: outer_again *)\nend\n\
+            (* @title: after *)\nlet after = 2\n");
+        let names : Vec<(&str, Vec<&str>)> = modules.iter()
+            .map(|m| (m.name.as_str(), m.items.iter().map(|i| i.title.as_str()).collect()))
+            .collect();
+        assert_eq!(names, vec![
+            ("lib", vec!["top", "after"]),
+            ("Outer", vec!["outer", "outer_again"]),
+            ("Inner", vec!["inner"]),
+        ]);
+    }
+}
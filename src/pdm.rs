@@ -1,10 +1,11 @@
-use std::{collections::hash_map, env::home_dir, path::PathBuf};
+use std::{collections::hash_map, env::home_dir, path::PathBuf, sync::OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use colored::{ColoredString, Colorize};
 use toml::Table;
 use termtree::*;
 
-use crate::{config::{self, Dependency}, logging::*};
+use crate::{cache, config::{self, Dependency}, logging::*, registry};
 
 /*pub fn validate_dependency( location : String ) -> Result<(), ColoredString> {
     debug("validate_dependency", &format!("Validating dependency \"{}\"", &location));
@@ -29,41 +30,53 @@ use crate::{config::{self, Dependency}, logging::*};
 }*/
 
 
+/// Clone a git dependency into the cache and pin the commit in Gup.lock
 pub fn add_dependency (url : String) -> Result<Dependency, ColoredString>
 {
-    let dep_path = get_hc_filepath()?.join("temp");
+    clone_dependency(url, None)
+}
+
+/// Clone a git dependency into the cache, at `pinned` or else the default branch, and record the commit in Gup.lock
+fn clone_dependency (url : String, pinned : Option<&str>) -> Result<Dependency, ColoredString>
+{
+    require_network(&format!("Cloning {url}"))?;
+    let staged = cache::StagingDir::new()?;
+    let dep_path = staged.path.clone();
 
-    git2::Repository::clone(&url, dep_path.clone())
+    let repo = git2::Repository::clone(&url, dep_path.clone())
+        .map_err(|e| e.to_string().red())?;
+    if let Some(pinned) = pinned {
+        let object = git2::Oid::from_str(pinned)
+            .and_then(|oid| repo.find_object(oid, None))
+            .map_err(|_| format!("{} Commit {pinned} pinned in Gup.lock is missing from {url}", "Lockfile error:".red()))?;
+        repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))
+            .and_then(|_| repo.set_head_detached(object.id()))
+            .map_err(|e| e.to_string().red())?;
+    }
+    let commit = repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|e| e.to_string().red())?
+        .id()
+        .to_string();
+    drop(repo);
+    // the commit is all the cache needs from the history
+    std::fs::remove_dir_all(dep_path.join(".git"))
         .map_err(|e| e.to_string().red())?;
 
-    
     // find package name from config file
     let dep_config_path : PathBuf = [dep_path.clone(), PathBuf::from("Config.toml")].iter().collect();
     let dep_config = crate::config::create_config_from_path(&dep_config_path.to_str().expect("Dependency path not found.").into())?;
     match &dep_config.package {
         Some(pack) => {
-            let package_name = format!("{}-{}", pack.name.chars().filter(|c| !c.is_whitespace()).collect::<String>(), pack.version);
-            let new_dep_path = get_hc_filepath()?.join(package_name.clone());
-            debug("PDM", &format!("Path to new dependency - \"{}\"", new_dep_path.to_string_lossy()));
-
-            match std::fs::exists(new_dep_path.clone()){
-                Ok(false) => {
-                    std::fs::rename(dep_path, new_dep_path)
-                        .map_err(|e| e.to_string().red())?;
-                },
-                Ok(true) => {
-                    std::fs::remove_dir_all(dep_path)
-                        .map_err(|e| e.to_string().red())?;
-                    warn("PDM", &format!("{} package already exists.", new_dep_path.to_string_lossy()))
-                },
-                Err(e) => return Err(format!("Unable to create directory: {}", e).into())
-            }
-            
             let return_value = Dependency {
                 name : pack.name.clone().chars().filter(|c| !c.is_whitespace()).collect(),
                 version : pack.version.clone(),
                 source : url,
             };
+            // cached by source and commit, so forks with the same name and version don't collide
+            let new_dep_path = cache::add_to_cache(&return_value, &commit, staged)?;
+            debug("PDM", &format!("Path to new dependency - \"{}\"", new_dep_path.to_string_lossy()));
+            lock_source_dep(&return_value, &commit)?;
             Ok(return_value)
         },
         None => Err("Dependency has invalid config!".into())
@@ -71,14 +84,94 @@ pub fn add_dependency (url : String) -> Result<Dependency, ColoredString>
     
 }
 
+/// Copy a local dependency into the cache, keyed on the checksum of its files
+pub fn add_path_dependency (path : &str) -> Result<Dependency, ColoredString>
+{
+    let cfg = config::create_config_from_path(&PathBuf::from(path).join(crate::CONFIG_PATH))?;
+    let pack = cfg.package.ok_or("Dependency has invalid config!".red())?;
+    let dep = Dependency {
+        name: pack.name.chars().filter(|c| !c.is_whitespace()).collect(),
+        version: pack.version,
+        source: path.into(),
+    };
+
+    let staged = cache::StagingDir::new()?;
+    cache::copy_tree(std::path::Path::new(path), &staged.path)
+        .map_err(|e| e.to_string().red())?;
+    let checksum = cache::tree_checksum(&staged.path)
+        .map_err(|e| e.to_string().red())?;
+    cache::add_to_cache(&dep, &checksum, staged)?;
+    lock_source_dep(&dep, &checksum)?;
+    Ok(dep)
+}
+
+/// Pin a git or path dependency in Gup.lock by its cache id (the commit, or the checksum of the copied files),
+/// with the checksum of its cached files. With `--locked` nothing is written, and a different id is an error.
+fn lock_source_dep(dep : &Dependency, id : &str) -> Result<(), ColoredString>
+{
+    let locked = crate::lock::Lockfile::load(&PathBuf::from(crate::LOCK_PATH))?
+        .find_exact(&dep.name, &dep.version, &dep.source)
+        .cloned();
+    let package = crate::lock::LockedPackage {
+        name: dep.name.clone(),
+        version: dep.version.clone(),
+        source: dep.source.clone(),
+        checksum: None,
+        commit: is_git_dep(dep)?.then(|| id.into()),
+        integrity: cache::CacheIndex::load()?.find_id(dep, id).and_then(|e| e.checksum.clone()),
+        signed_by: None,
+        deprecated: None,
+    };
+    if locked.as_ref() == Some(&package) {
+        return Ok(());
+    }
+    if is_locked() {
+        return match locked.and_then(|p| p.commit.or(p.integrity)) {
+            Some(pinned) if pinned != id => Err(format!("{} {} from {} no longer matches Gup.lock, but --locked was passed. Run a build without it to update Gup.lock",
+                "Lockfile error:".red(), dep.name, dep.source).into()),
+            _ => Ok(()),
+        };
+    }
+    crate::lock::record(package)
+}
+
+/// Cache id of a git or path dependency pinned in Gup.lock: the commit, or the checksum of the copied files
+pub fn locked_id(dep : &Dependency) -> Result<Option<String>, ColoredString>
+{
+    if dep.source.starts_with("registry+") {
+        return Ok(None);
+    }
+    Ok(crate::lock::Lockfile::load(&PathBuf::from(crate::LOCK_PATH))?
+        .find_exact(&dep.name, &dep.version, &dep.source)
+        .and_then(|p| p.commit.clone().or(p.integrity.clone())))
+}
+
+/// Whether a dependency with a `source` is cloned with git rather than copied from a local path
+fn is_git_dep(dep : &Dependency) -> Result<bool, ColoredString>
+{
+    Ok(!dep.source.starts_with("registry+") && !std::fs::exists(&dep.source).map_err(|e| e.to_string())?)
+}
+
 pub fn get_dep_cfg(dep : Dependency) -> Result<crate::Config, ColoredString>
 {
-    let name_version = get_dep_filename(&dep)?;
-    let full_path : PathBuf = [get_hc_filepath()?, name_version.into(), "Config.toml".into()].iter().collect();
+    let full_path : PathBuf = get_dep_dir(&dep)?.join("Config.toml");
     debug("get_dep_cfg", &format!("Getting config from {}", full_path.to_string_lossy()));
     config::create_config_from_path(&full_path)
 }
 
+/// Directory a dependency is vendored or cached in
+pub fn get_dep_dir(dep : &Dependency) -> Result<PathBuf, ColoredString>
+{
+    if let Some(dir) = crate::vendor::vendored_dir(dep)? {
+        return Ok(dir);
+    }
+    let cached = match locked_id(dep)? {
+        Some(id) => cache::cached_dir_with_id(dep, &id)?,
+        None => cache::cached_dir(dep)?,
+    };
+    cached.ok_or(format!("{} from {} is missing from the cache", get_dep_filename(dep)?, dep.source).red())
+}
+
 pub fn get_dep_filename(dep : &Dependency) -> Result<String, ColoredString>
 {
     Ok(format!("{}-{}", dep.name.chars().filter(|c| !c.is_whitespace()).collect::<String>(), dep.version))
@@ -100,26 +193,119 @@ pub fn get_dep_tree(cfg : crate::Config) -> Result<Tree<String>, ColoredString>
         {
             for dep in deps
             {    
-                let new_dep = table_to_dep(dep.1.as_table().expect("Unable to create table from dependency"))?;
-                if hm.contains_key(&get_dep_filename(&new_dep)?)
+                let new_dep = entry_to_dep(dep.0, dep.1)?;
+                if let hash_map::Entry::Vacant(entry) = hm.entry(format!("{} {}", get_dep_filename(&new_dep)?, new_dep.source))
                 {
-                    tree.push(format!("{} *", get_dep_filename(&new_dep)?));
-                }else {
-                    hm.insert(get_dep_filename(&new_dep)?, true);
+                    entry.insert(true);
+                    ensure_dep_cached(&new_dep)?;
                     let child_subtree = get_tree_recursive(get_dep_cfg(new_dep)?, hm)?;
                     tree.push(child_subtree);
+                }else {
+                    tree.push(format!("{} *", get_dep_filename(&new_dep)?));
                 }
                 
             }   
         }
         Ok(tree)
     }
-    Ok(get_tree_recursive(cfg, &mut hash_map::HashMap::new())?)
+    get_tree_recursive(cfg, &mut hash_map::HashMap::new())
 
     
 
 }
 
+/// Every package in the dependency tree of a config, each listed once
+pub fn get_dep_list(cfg : &crate::Config) -> Result<Vec<Dependency>, ColoredString>
+{
+    fn get_list_recursive(cfg : &crate::Config, list : &mut Vec<Dependency>) -> Result<(), ColoredString>
+    {
+        if let Some(deps) = cfg.dependencies.as_ref()
+        {
+            for dep in deps
+            {
+                let new_dep = entry_to_dep(dep.0, dep.1)?;
+                if !list.iter().any(|d| d.name == new_dep.name && d.version == new_dep.version && d.source == new_dep.source)
+                {
+                    ensure_dep_cached(&new_dep)?;
+                    let dep_cfg = get_dep_cfg(new_dep.clone())?;
+                    list.push(new_dep);
+                    get_list_recursive(&dep_cfg, list)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    let mut list = Vec::new();
+    get_list_recursive(cfg, &mut list)?;
+    Ok(list)
+}
+
+/// Turn a `[dependencies]` entry into a dependency.
+/// Entries with a `source` are git or path dependencies, anything else (`name = "^1.0"` or a table with a
+/// `version` and optional `registry`) is resolved through a registry.
+pub fn entry_to_dep (name : &str, value : &toml::Value) -> Result<Dependency, ColoredString>
+{
+    match value {
+        toml::Value::String(requirement) => registry::resolve_registry_dep(name, requirement, None),
+        toml::Value::Table(table) if !table.contains_key("source") => {
+            let requirement = table.get("version")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| "Registry dependency missing or invalid 'version' field".red())?;
+            let package = table.get("name").and_then(|v| v.as_str()).unwrap_or(name);
+            registry::resolve_registry_dep(package, requirement, table.get("registry").and_then(|v| v.as_str()))
+        },
+        toml::Value::Table(table) => table_to_dep(table),
+        _ => Err(format!("Dependency \"{name}\" should be a version string or a table").red()),
+    }
+}
+
+/// Make sure a dependency is in the cache, copying, cloning or downloading it from its source if it isn't
+pub fn ensure_dep_cached(dep : &Dependency) -> Result<(), ColoredString>
+{
+    if crate::vendor::vendored_dir(dep)?.is_some() {
+        return Ok(());
+    }
+    if dep.source.starts_with("registry+") {
+        if cache::cached_dir(dep)?.is_none() {
+            registry::refetch(dep)?;
+        }
+        return Ok(());
+    }
+    if is_git_dep(dep)? {
+        return ensure_git_dep_cached(dep);
+    }
+    match locked_id(dep)? {
+        Some(id) if cache::cached_dir_with_id(dep, &id)?.is_some() => Ok(()),
+        locked => match cache::CacheIndex::load()?.find(dep) {
+            // pin the copy made before Gup.lock recorded path dependencies
+            Some(entry) if locked.is_none() && cache::cached_dir_with_id(dep, &entry.id)?.is_some() => lock_source_dep(dep, &entry.id),
+            _ => {
+                debug("ensure_dep_cached", &format!("Copying {} into the cache", dep.source));
+                add_path_dependency(&dep.source).map(|_| ())
+            },
+        },
+    }
+}
+
+/// Make sure the commit of a git dependency pinned in Gup.lock is cached, or pin the newest cached commit
+fn ensure_git_dep_cached(dep : &Dependency) -> Result<(), ColoredString>
+{
+    if let Some(commit) = locked_id(dep)? {
+        if cache::cached_dir_with_id(dep, &commit)?.is_none() {
+            clone_dependency(dep.source.clone(), Some(&commit))?;
+        }
+        return Ok(());
+    }
+    if is_locked() {
+        return Err(format!("{} {} from {} is not pinned in Gup.lock, but --locked was passed. Run a build without it to update Gup.lock",
+            "Lockfile error:".red(), dep.name, dep.source).into());
+    }
+    match cache::CacheIndex::load()?.find(dep) {
+        Some(entry) if cache::cached_dir_with_id(dep, &entry.id)?.is_some() => lock_source_dep(dep, &entry.id),
+        _ => clone_dependency(dep.source.clone(), None).map(|_| ()),
+    }
+}
+
 pub fn table_to_dep (table : &Table) -> Result<Dependency, ColoredString>
 {
     Ok(Dependency {
@@ -146,43 +332,137 @@ pub fn print_dep_tree () -> Result<(), ColoredString>
     Ok(())
 }
 
-pub fn get_hc_filepath() -> Result<PathBuf, ColoredString>
+/// Cache directory given with `--cache-dir`
+static CACHE_DIR : OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_cache_dir(dir : PathBuf)
 {
-    if let Some(home) = home_dir() 
-    {
-        let hc_path = home.join(".hc");
-
-        // create hc directory if not exist
-        match std::fs::exists(&hc_path) {
-            Ok(true) => {},
-            Ok(false) => {
-                std::fs::create_dir(&hc_path)
-                    .map_err(|e| e.to_string().red())?;
-            },
-            Err(e) => return Err(format!("Unable to create directory: {}", e).into())
-        }
+    // made absolute, since publish builds from a scratch directory
+    let _ = CACHE_DIR.set(std::path::absolute(&dir).unwrap_or(dir));
+}
+
+/// Set by `--offline` and `--frozen`
+static OFFLINE : AtomicBool = AtomicBool::new(false);
+/// Set by `--locked` and `--frozen`
+static LOCKED : AtomicBool = AtomicBool::new(false);
 
-        Ok(hc_path)
-    }else {
-        Err("Unable to find home directory.".into())
+pub fn set_offline(offline : bool)
+{
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn set_locked(locked : bool)
+{
+    LOCKED.store(locked, Ordering::Relaxed);
+}
+
+/// Whether Gup.lock must be used as is, without resolving or writing anything new
+pub fn is_locked() -> bool
+{
+    LOCKED.load(Ordering::Relaxed)
+}
+
+pub fn is_offline() -> bool
+{
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Fail if network access is disabled. `what` describes what needed the network.
+pub fn require_network(what : &str) -> Result<(), ColoredString>
+{
+    if is_offline() {
+        return Err(format!("{} {what} needs the network, but --offline was passed", "Offline error:".red()).into());
     }
+    Ok(())
+}
 
+/// Value of an environment variable, treating an empty one as unset
+fn env_path(var : &str) -> Option<PathBuf>
+{
+    std::env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Whether the XDG layout is used, decided once, before gup creates `~/.hc`
+static XDG_LAYOUT : OnceLock<bool> = OnceLock::new();
+
+/// `$GUP_HOME`, else `~/.hc` if it exists or no XDG variable is set, else `$<xdg_var>/gup`.
+/// An unset XDG variable falls back to its default, `~/<default>`.
+fn gup_dir(xdg_var : &str, default : &str) -> Result<PathBuf, ColoredString>
+{
+    if let Some(gup_home) = env_path("GUP_HOME") {
+        return Ok(gup_home);
+    }
+    let home = home_dir().ok_or("Unable to find home directory.".red())?;
+    let xdg = *XDG_LAYOUT.get_or_init(|| {
+        !home.join(".hc").exists() && (env_path("XDG_CONFIG_HOME").is_some() || env_path("XDG_CACHE_HOME").is_some())
+    });
+    if !xdg {
+        return Ok(home.join(".hc"));
+    }
+    Ok(env_path(xdg_var).unwrap_or(home.join(default)).join("gup"))
+}
+
+/// Create a directory if it doesn't exist
+fn ensure_dir(path : PathBuf) -> Result<PathBuf, ColoredString>
+{
+    match std::fs::exists(&path) {
+        Ok(true) => {},
+        Ok(false) => {
+            // another gup may be creating it at the same time
+            std::fs::create_dir_all(&path)
+                .map_err(|e| e.to_string().red())?;
+        },
+        Err(e) => return Err(format!("Unable to create directory: {}", e).into())
+    }
+    Ok(path)
+}
+
+/// Directory for the global config, credentials and registered projects
+pub fn get_hc_filepath() -> Result<PathBuf, ColoredString>
+{
+    ensure_dir(gup_dir("XDG_CONFIG_HOME", ".config")?)
+}
+
+/// Directory for cached packages and registry clones. `--cache-dir` overrides it.
+pub fn get_cache_filepath() -> Result<PathBuf, ColoredString>
+{
+    match CACHE_DIR.get() {
+        Some(dir) => ensure_dir(dir.clone()),
+        None => ensure_dir(gup_dir("XDG_CACHE_HOME", ".cache")?),
+    }
 }
 
 pub fn update_dependencies() -> Result<(), ColoredString>
 {
     for dep_table in config::create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?.dependencies.unwrap()
     {
-        let dep = table_to_dep(dep_table.1.as_table().expect("Dependency entry should be a table!"))?;
-        let url = url::Url::parse(&dep.source)
-            .map_err(|e|e.to_string())?;
+        if !dep_table.1.as_table().is_some_and(|t| t.contains_key("source"))
+        {
+            // forget the locked version so the registry dependency resolves to the newest match
+            let lock_path = PathBuf::from(crate::LOCK_PATH);
+            let mut lock = crate::lock::Lockfile::load(&lock_path)?;
+            let package = dep_table.1.get("name").and_then(|v| v.as_str()).unwrap_or(&dep_table.0);
+            lock.packages.retain(|p| !(p.name == package && p.source.starts_with("registry+")));
+            lock.save(&lock_path)?;
 
-        if url.has_host()
+            let dep = entry_to_dep(&dep_table.0, &dep_table.1)?;
+            info("Update", &format!("{} is at {}", dep.name, dep.version));
+            continue;
+        }
+        let dep = table_to_dep(dep_table.1.as_table().expect("Dependency entry should be a table!"))?;
+        if is_git_dep(&dep)?
         {
-            let dest : PathBuf = get_hc_filepath()?.join(get_dep_filename(&dep)?);
-            git2::Repository::clone(&url.to_string(), dest)
-                .map_err(|e|e.to_string())?;
+            // a new commit gets its own cache entry, and replaces the one pinned in Gup.lock
+            add_dependency(dep.source)?;
         }
     }
+
+    // drop the versions nothing depends on anymore, several versions of one package stay if they are all used
+    let config = config::create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let deps = get_dep_list(&config)?;
+    let lock_path = PathBuf::from(crate::LOCK_PATH);
+    let mut lock = crate::lock::Lockfile::load(&lock_path)?;
+    lock.packages.retain(|p| deps.iter().any(|d| d.name == p.name && d.version == p.version && d.source == p.source));
+    lock.save(&lock_path)?;
     Ok(())
 }
\ No newline at end of file
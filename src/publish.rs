@@ -0,0 +1,272 @@
+use std::path::{Component, Path, PathBuf};
+
+use colored::{ColoredString, Colorize};
+
+use crate::cache::StagingDir;
+use crate::config::{create_config_from_path, Config};
+use crate::doc::{render, DocFormat};
+use crate::logging::*;
+use crate::parse::extract_docs;
+use crate::registry::{find_registry, sha256_hex, unpack_archive, IndexEntry, IndexFile, Registry, RegistryKind};
+
+/// Path of a project file inside a package archive, without `.` components
+fn archive_path(path : &str) -> Result<PathBuf, ColoredString>
+{
+    let path : PathBuf = Path::new(path).components().filter(|c| *c != Component::CurDir).collect();
+    if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("{} \"{}\" is outside of the project and can't be published", "Publish error:".red(), path.to_string_lossy()).into());
+    }
+    Ok(path)
+}
+
+/// Append one file to an archive with fixed metadata, so the same project always packs to the same bytes
+fn append_file(builder : &mut tar::Builder<flate2::write::GzEncoder<Vec<u8>>>, path : &Path, contents : &[u8]) -> Result<(), ColoredString>
+{
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, path, contents)
+        .map_err(|e| format!("{} Unable to add {} to the archive: {e}", "Publish error:".red(), path.to_string_lossy()).into())
+}
+
+/// Pack Config.toml, the infiles and freshly generated docs into a `.tar.gz` archive
+pub fn pack_project(cfg : &Config) -> Result<Vec<u8>, ColoredString>
+{
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    let mut files = vec![String::from(crate::CONFIG_PATH)];
+    files.extend(cfg.build.infiles.iter().cloned());
+    for file in &files {
+        debug("pack_project", &format!("Adding {file}"));
+        let contents = std::fs::read(file)
+            .map_err(|e| format!("{} Unable to read \"{file}\": {e}", "Publish error:".red()))?;
+        append_file(&mut builder, &archive_path(file)?, &contents)?;
+    }
+
+    let docfile = cfg.build.docfile.clone().unwrap_or(String::from(crate::DOCS_PATH));
+    let docs = extract_docs(cfg, Path::new("."), &mut Vec::new())?;
+    append_file(&mut builder, &archive_path(&docfile)?, render(&docs, DocFormat::Markdown, None)?.as_bytes())?;
+
+    builder.into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| format!("{} Unable to write the archive: {e}", "Publish error:".red()).into())
+}
+
+/// Unpack an archive into a scratch directory and build its files, so files missing from the package are caught
+fn verify_archive(archive : &[u8]) -> Result<(), ColoredString>
+{
+    let staged = StagingDir::new()?;
+    unpack_archive(archive, &staged.path)?;
+    let cfg = create_config_from_path(&staged.path.join(crate::CONFIG_PATH))?;
+    crate::build::build_from(&cfg, &staged.path)
+}
+
+/// Write an index file into a local registry, committing and pushing it for git registries
+fn write_index(dir : &Path, git : bool, index : &IndexFile, message : &str) -> Result<(), ColoredString>
+{
+    let contents = toml::to_string(index).map_err(|e| e.to_string())?;
+    std::fs::write(dir.join("index").join(format!("{}.toml", index.name)), contents).map_err(|e| e.to_string().red())?;
+
+    // git registries are updated through a commit on the clone
+    if git {
+        let repo = git2::Repository::open(dir).map_err(|e| e.to_string().red())?;
+        commit_and_push(&repo, message)?;
+    }
+    Ok(())
+}
+
+/// Write an archive and its index entry into a local registry, or upload it to an HTTP registry
+fn add_to_registry(registry : &Registry, index : IndexFile, archive : &[u8], entry_path : &str) -> Result<(), ColoredString>
+{
+    let version = index.versions.last().map(|v| v.version.clone()).unwrap_or_default();
+    let (dir, git) = match &registry.kind {
+        RegistryKind::Local { dir, git } => (dir, *git),
+        // HTTP registries update their own index, so a signature is sent alongside the archive
+        RegistryKind::Http { url } => {
+            let mut headers = Vec::new();
+            if let Some(entry) = index.versions.last() && let (Some(signed_by), Some(signature)) = (&entry.signed_by, &entry.signature) {
+                headers.push(("X-Gup-Signed-By", signed_by.as_str()));
+                headers.push(("X-Gup-Signature", signature.as_str()));
+            }
+            return send(registry, "PUT", &format!("{url}/api/v1/packages/{}/{version}", index.name), &headers, archive,
+                &format!("{} {version} is already published to \"{}\"", index.name, registry.name));
+        },
+    };
+
+    let archive_file = dir.join(entry_path);
+    if let Some(parent) = archive_file.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(&archive_file, archive).map_err(|e| e.to_string().red())?;
+    write_index(dir, git, &index, &format!("Publish {} {version}", index.name))
+}
+
+/// Send an authenticated request to an HTTP registry, with the token from `gup login`
+fn send(registry : &Registry, method : &str, endpoint : &str, headers : &[(&str, &str)], body : &[u8], conflict : &str) -> Result<(), ColoredString>
+{
+    crate::pdm::require_network(&format!("{method} {endpoint}"))?;
+    let token = registry.token()?
+        .ok_or(format!("{} No token for registry \"{}\", run `gup login --registry {}` first", "Registry error:".red(), registry.name, registry.name))?;
+    debug("Registry", &format!("{method} {endpoint}"));
+    let mut request = ureq::request(method, endpoint)
+        .set("Authorization", &format!("Bearer {token}"))
+        .set("Content-Type", "application/octet-stream");
+    for (header, value) in headers {
+        request = request.set(header, value);
+    }
+    match request.send_bytes(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(401 | 403, _)) =>
+            Err(format!("{} Registry \"{}\" rejected the token, run `gup login` again", "Registry error:".red(), registry.name).into()),
+        Err(ureq::Error::Status(409, _)) => Err(format!("{} {conflict}", "Registry error:".red()).into()),
+        Err(ureq::Error::Status(code, response)) =>
+            Err(format!("{} Registry responded {code}: {}", "Registry error:".red(), response.into_string().unwrap_or_default()).into()),
+        Err(e) => Err(format!("{} {e}", "Registry error:".red()).into()),
+    }
+}
+
+/// Mark a published version as yanked, so it is no longer picked for new resolutions. `undo` makes it selectable again.
+pub fn yank(package : &str, registry_name : Option<&str>, undo : bool) -> Result<(), ColoredString>
+{
+    let (name, version) = package.split_once('@')
+        .ok_or(format!("{} Expected NAME@VERSION, got \"{package}\"", "Yank error:".red()))?;
+    let registry = find_registry(registry_name)?;
+    let action = if undo { "Unyanked" } else { "Yanked" };
+
+    match &registry.kind {
+        RegistryKind::Local { dir, git } => {
+            let mut index = registry.index(name)?;
+            let entry = index.versions.iter_mut().find(|e| e.version == version)
+                .ok_or(format!("{} {name} {version} is not published to \"{}\"", "Yank error:".red(), registry.name))?;
+            if entry.yanked != undo {
+                info("Yank", &format!("{name} {version} is already {}", action.to_lowercase()));
+                return Ok(());
+            }
+            entry.yanked = !undo;
+            write_index(dir, *git, &index, &format!("{action} {name} {version}"))?;
+        },
+        RegistryKind::Http { url } => {
+            let method = if undo { "PUT" } else { "DELETE" };
+            send(&registry, method, &format!("{url}/api/v1/packages/{name}/{version}/yank"), &[], &[], "")?;
+        },
+    }
+    success(&format!("{action} {name} {version} in \"{}\"", registry.name));
+    Ok(())
+}
+
+/// Set the deprecation message of a package, or remove it with `None`
+pub fn deprecate(name : &str, message : Option<&str>, registry_name : Option<&str>) -> Result<(), ColoredString>
+{
+    let registry = find_registry(registry_name)?;
+    match &registry.kind {
+        RegistryKind::Local { dir, git } => {
+            let mut index = registry.index(name)?;
+            index.deprecated = message.map(String::from);
+            let commit = match message { Some(_) => "Deprecate", None => "Undeprecate" };
+            write_index(dir, *git, &index, &format!("{commit} {name}"))?;
+        },
+        RegistryKind::Http { url } => {
+            let endpoint = format!("{url}/api/v1/packages/{name}/deprecation");
+            match message {
+                Some(message) => send(&registry, "PUT", &endpoint, &[], message.as_bytes(), "")?,
+                None => send(&registry, "DELETE", &endpoint, &[], &[], "")?,
+            }
+        },
+    }
+    match message {
+        Some(_) => success(&format!("Deprecated {name} in \"{}\"", registry.name)),
+        None => success(&format!("Removed the deprecation of {name} in \"{}\"", registry.name)),
+    }
+    Ok(())
+}
+
+fn commit_and_push(repo : &git2::Repository, message : &str) -> Result<(), ColoredString>
+{
+    let git = |e : git2::Error| format!("{} {e}", "Publish error:".red());
+    let mut index = repo.index().map_err(git)?;
+    index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).map_err(git)?;
+    index.write().map_err(git)?;
+    let tree = repo.find_tree(index.write_tree().map_err(git)?).map_err(git)?;
+    let signature = repo.signature()
+        .or_else(|_| git2::Signature::now("gup", "gup@localhost"))
+        .map_err(git)?;
+    let parent = repo.head().and_then(|h| h.peel_to_commit()).map_err(git)?;
+    repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &[&parent]).map_err(git)?;
+
+    let head = repo.head().map_err(git)?;
+    let branch = head.name().ok_or("Registry HEAD is not a branch".red())?;
+    repo.find_remote("origin")
+        .and_then(|mut remote| remote.push(&[format!("{branch}:{branch}")], None))
+        .map_err(git)?;
+    Ok(())
+}
+
+pub fn publish(registry_name : Option<&str>, dry_run : bool, verify : bool, sign : bool) -> Result<(), ColoredString>
+{
+    // creating the config runs validate_config
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let pack = cfg.package.as_ref().ok_or(format!("{} Config has no [package]", "Publish error:".red()))?;
+    let name : String = pack.name.chars().filter(|c| !c.is_whitespace()).collect();
+    semver::Version::parse(&pack.version)
+        .map_err(|e| format!("{} Invalid [package].version \"{}\": {e}", "Publish error:".red(), pack.version))?;
+    for infile in &cfg.build.infiles {
+        if !Path::new(infile).exists() {
+            return Err(format!("{} Infile \"{infile}\" does not exist", "Publish error:".red()).into());
+        }
+    }
+    for (dep, value) in cfg.dependencies.iter().flatten() {
+        if value.as_table().and_then(|t| t.get("source")).and_then(|s| s.as_str()).is_some_and(|s| Path::new(s).exists()) {
+            warn("Publish", &format!("Dependency \"{dep}\" is a local path, which users of this package won't have"));
+        }
+    }
+
+    let registry = find_registry(registry_name)?;
+    let mut index = registry.find_index(&name)?
+        .unwrap_or(IndexFile { name: name.clone(), ..Default::default() });
+    if index.versions.iter().any(|v| v.version == pack.version) {
+        return Err(format!("{} {name} {} is already published to \"{}\"", "Publish error:".red(), pack.version, registry.name).into());
+    }
+
+    let timer = start_step("Packaging");
+    let archive = pack_project(&cfg)?;
+    elapsed("Packaging", timer);
+    if verify {
+        let timer = start_step("Verifying");
+        verify_archive(&archive)?;
+        elapsed("Verifying", timer);
+    }
+
+    let checksum = sha256_hex(&archive);
+    let (signed_by, signature) = match sign {
+        true => crate::sign::sign_package(&name, &pack.version, &checksum).map(|(key, signature)| (Some(key), Some(signature)))?,
+        false => (None, None),
+    };
+    let entry = IndexEntry {
+        version: pack.version.clone(),
+        checksum,
+        archive: format!("archives/{name}-{}.tar.gz", pack.version),
+        dependencies: cfg.dependencies.clone().unwrap_or_default().into_iter().collect(),
+        yanked: false,
+        signed_by,
+        signature,
+    };
+    info("Publish", &format!("{name} {} ({} bytes, sha256 {})", entry.version, archive.len(), entry.checksum));
+    if let Some(signed_by) = &entry.signed_by {
+        info("Publish", &format!("Signed with {signed_by}"));
+    }
+    if dry_run {
+        success("Dry run complete, nothing was published");
+        return Ok(());
+    }
+
+    if pack.description.is_some() {
+        index.description = pack.description.clone();
+    }
+    let archive_entry = entry.archive.clone();
+    index.versions.push(entry);
+    add_to_registry(&registry, index, &archive, &archive_entry)?;
+    success(&format!("Published {name} {} to \"{}\"", pack.version, registry.name));
+    Ok(())
+}
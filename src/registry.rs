@@ -0,0 +1,531 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use colored::{ColoredString, Colorize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{create_config_from_path, load_global_config, Dependency};
+use crate::lock::{LockedPackage, Lockfile};
+use crate::logging::*;
+use crate::pdm::{get_cache_filepath, get_hc_filepath, is_locked, is_offline, require_network};
+
+/// Name of the registry used when a dependency doesn't name one
+pub const DEFAULT_REGISTRY: &str = "default";
+
+/// Index file of one package in a registry, stored at `index/<name>.toml`, or served as `index/<name>.json` by HTTP registries
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct IndexFile {
+    pub name: String,
+    pub description: Option<String>,
+    /// Deprecation message, shown as a warning when the package is added or built
+    pub deprecated: Option<String>,
+    #[serde(default, rename = "version")]
+    pub versions: Vec<IndexEntry>,
+}
+
+/// One published version of a package
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Clone)]
+pub struct IndexEntry {
+    pub version: String,
+    /// sha256 of the archive
+    pub checksum: String,
+    /// Archive location, relative to the registry root or URL
+    pub archive: String,
+    /// Dependencies of this version, as written in its Config.toml
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, toml::Value>,
+    /// Yanked versions are never picked for new resolutions, but locked ones still download
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    /// Hex encoded ed25519 public key of the publisher who signed the checksum, see `gup publish --sign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_by: Option<String>,
+    /// Hex encoded ed25519 signature of `checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Where a registry's index and archives live
+pub enum RegistryKind {
+    /// A directory, or a clone of a git registry in the cache's `registries` directory
+    Local { dir: PathBuf, git: bool },
+    /// An HTTP registry with a sparse index, written as `sparse+URL`
+    Http { url: String },
+}
+
+/// A package registry
+pub struct Registry {
+    pub name: String,
+    pub location: String,
+    pub kind: RegistryKind,
+}
+
+/// Source string recorded for packages from a registry location
+pub fn registry_source(location : &str) -> String
+{
+    format!("registry+{location}")
+}
+
+/// Whether a registry location is a git repository rather than a directory
+pub fn is_git_location(location : &str) -> bool
+{
+    location.starts_with("git+") || url::Url::parse(location).is_ok_and(|u| u.has_host())
+}
+
+/// Every configured registry by name, where Config.toml overrides the global config
+pub fn registry_locations() -> Result<BTreeMap<String, String>, ColoredString>
+{
+    let mut registries = load_global_config()?.registries.unwrap_or_default();
+    if let Some(project) = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)).ok().and_then(|c| c.registries) {
+        registries.extend(project);
+    }
+    Ok(registries)
+}
+
+/// Look up the location of a registry by name in Config.toml, then in the global config
+pub fn find_registry_location(name : Option<&str>) -> Result<(String, String), ColoredString>
+{
+    let name = name.unwrap_or(DEFAULT_REGISTRY);
+    let location = registry_locations()?
+        .remove(name)
+        .ok_or(format!("{} No registry named \"{name}\" in Config.toml or {}", "Registry error:".red(),
+            get_hc_filepath()?.join("config.toml").to_string_lossy()))?;
+    Ok((name.into(), location))
+}
+
+/// Look up a registry by name in Config.toml, then in the global config
+pub fn find_registry(name : Option<&str>) -> Result<Registry, ColoredString>
+{
+    let (name, location) = find_registry_location(name)?;
+    open_registry(&name, &location)
+}
+
+/// Open a registry at a location, cloning or updating it first if it is a git repository
+pub fn open_registry(name : &str, location : &str) -> Result<Registry, ColoredString>
+{
+    if let Some(url) = location.strip_prefix("sparse+") {
+        return Ok(Registry { name: name.into(), location: location.into(), kind: RegistryKind::Http { url: url.trim_end_matches('/').into() } });
+    }
+
+    let git = is_git_location(location);
+    let dir = if git {
+        sync_git_repo(location, "registry", "registries")?
+    } else {
+        PathBuf::from(location)
+    };
+    if !dir.join("index").is_dir() {
+        return Err(format!("{} \"{}\" has no index directory", "Registry error:".red(), dir.to_string_lossy()).into());
+    }
+    Ok(Registry { name: name.into(), location: location.into(), kind: RegistryKind::Local { dir, git } })
+}
+
+/// Packages already warned about by this process, since a build resolves each dependency more than once
+static DEPRECATED : Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn warn_deprecated(name : &str, message : &str)
+{
+    let mut warned = DEPRECATED.lock().unwrap_or_else(|e| e.into_inner());
+    if !warned.iter().any(|n| n == name) {
+        warn("Registry", &format!("{name} is deprecated: {message}"));
+        warned.push(name.into());
+    }
+}
+
+/// Open a registry without using the network: a directory, or the existing clone of a git registry.
+/// Returns `None` for HTTP registries and git registries that haven't been cloned.
+fn open_local_registry(name : &str, location : &str) -> Result<Option<Registry>, ColoredString>
+{
+    if location.starts_with("sparse+") {
+        return Ok(None);
+    }
+    let git = is_git_location(location);
+    let dir = if git { clone_dir(location, "registries")? } else { PathBuf::from(location) };
+    if !dir.join("index").is_dir() {
+        return Ok(None);
+    }
+    Ok(Some(Registry { name: name.into(), location: location.into(), kind: RegistryKind::Local { dir, git } }))
+}
+
+/// Directory a git repository is cloned to, in the cache's `parent` directory
+fn clone_dir(location : &str, parent : &str) -> Result<PathBuf, ColoredString>
+{
+    let url = location.strip_prefix("git+").unwrap_or(location);
+    let dir_name : String = url.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect();
+    Ok(get_cache_filepath()?.join(parent).join(dir_name))
+}
+
+/// Clones already updated by this process, so parallel and repeated lookups fetch once
+static SYNCED : Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Clone a git repository (a registry or advisory database, named by `kind`) into the cache's `parent` directory,
+/// or fast-forward an existing clone, and return its directory
+pub fn sync_git_repo(location : &str, kind : &str, parent : &str) -> Result<PathBuf, ColoredString>
+{
+    let url = location.strip_prefix("git+").unwrap_or(location);
+    let dir = clone_dir(location, parent)?;
+
+    // held until the clone is up to date, so threads don't update it at the same time
+    let mut synced = SYNCED.lock().unwrap_or_else(|e| e.into_inner());
+    if synced.contains(&dir) {
+        return Ok(dir);
+    }
+
+    if is_offline() && dir.exists() {
+        debug("Registry", &format!("Using the existing clone of {url} offline"));
+    } else if !dir.exists() {
+        require_network(&format!("Cloning {kind} {url}"))?;
+        info("Registry", &format!("Cloning {kind} {url}"));
+        git2::Repository::clone(url, &dir)
+            .map_err(|e| e.to_string().red())?;
+    } else {
+        require_network(&format!("Updating {kind} {url}"))?;
+        debug("Registry", &format!("Updating {kind} {url}"));
+        let repo = git2::Repository::open(&dir).map_err(|e| e.to_string().red())?;
+        repo.find_remote("origin")
+            .and_then(|mut remote| remote.fetch(&[] as &[&str], None, None))
+            .map_err(|e| e.to_string().red())?;
+        let head = repo.revparse_single("FETCH_HEAD").map_err(|e| e.to_string().red())?;
+        repo.reset(&head, git2::ResetType::Hard, None).map_err(|e| e.to_string().red())?;
+    }
+    synced.push(dir.clone());
+    Ok(dir)
+}
+
+/// Tokens for HTTP registries, stored in `~/.hc/credentials.toml` and keyed by registry location
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct Credentials {
+    #[serde(default)]
+    pub tokens: BTreeMap<String, String>,
+}
+
+fn credentials_path() -> Result<PathBuf, ColoredString>
+{
+    Ok(get_hc_filepath()?.join("credentials.toml"))
+}
+
+pub fn load_credentials() -> Result<Credentials, ColoredString>
+{
+    let path = credentials_path()?;
+    if !path.exists() {
+        return Ok(Credentials::default());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{} {}", "Credentials error:\n".red(), e))?;
+    toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read credentials".red()).into())
+}
+
+/// Store the token for a registry location, readable only by the current user
+pub fn save_token(location : &str, token : &str) -> Result<(), ColoredString>
+{
+    let mut credentials = load_credentials()?;
+    credentials.tokens.insert(location.into(), token.into());
+    let contents = toml::to_string(&credentials).map_err(|e| e.to_string())?;
+
+    let path = credentials_path()?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("{} Unable to write \"{}\": {e}", "Credentials error:".red(), path.to_string_lossy()).into())
+}
+
+/// Store a token for a registry, reading it from stdin if not given
+pub fn login(registry : Option<&str>, token : Option<String>) -> Result<(), ColoredString>
+{
+    let (name, location) = find_registry_location(registry)?;
+    if !location.starts_with("sparse+") {
+        warn("Login", &format!("Registry \"{name}\" is not an HTTP registry and won't use the token"));
+    }
+    let token = match token {
+        Some(token) => token,
+        None => {
+            println!("Paste the token for \"{name}\" below");
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map_err(|e| e.to_string().red())?;
+            line
+        },
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(format!("{} Token is empty", "Login error:".red()).into());
+    }
+    save_token(&location, token)?;
+    success(&format!("Saved token for \"{name}\" to {}", credentials_path()?.to_string_lossy()));
+    Ok(())
+}
+
+/// GET a URL from an HTTP registry. Returns `None` on 404.
+fn http_get(url : &str) -> Result<Option<Vec<u8>>, ColoredString>
+{
+    require_network(&format!("GET {url}"))?;
+    debug("Registry", &format!("GET {url}"));
+    match ureq::get(url).call() {
+        Ok(response) => {
+            let mut bytes = Vec::new();
+            response.into_reader().read_to_end(&mut bytes)
+                .map_err(|e| format!("{} Unable to read {url}: {e}", "Registry error:".red()))?;
+            Ok(Some(bytes))
+        },
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(e) => Err(format!("{} {url}: {e}", "Registry error:".red()).into()),
+    }
+}
+
+impl Registry {
+    pub fn source(&self) -> String
+    {
+        registry_source(&self.location)
+    }
+
+    /// Read the index file of a package, or `None` if the registry doesn't have it
+    pub fn find_index(&self, name : &str) -> Result<Option<IndexFile>, ColoredString>
+    {
+        let invalid = |e : String| format!("{e}{}", format!("\nInvalid index file for \"{name}\"").red());
+        match &self.kind {
+            RegistryKind::Local { dir, .. } => {
+                let path = dir.join("index").join(format!("{name}.toml"));
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let contents = std::fs::read_to_string(&path).map_err(|e| e.to_string().red())?;
+                toml::from_str(&contents).map(Some).map_err(|e| invalid(e.to_string()).into())
+            },
+            RegistryKind::Http { url } => match http_get(&format!("{url}/index/{name}.json"))? {
+                Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| invalid(e.to_string()).into()),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Read the index file of a package
+    pub fn index(&self, name : &str) -> Result<IndexFile, ColoredString>
+    {
+        self.find_index(name)?
+            .ok_or(format!("{} Package \"{name}\" not found in registry \"{}\"", "Registry error:".red(), self.name).into())
+    }
+
+    /// Index files of packages whose name or description contains `query`, ignoring case
+    pub fn search(&self, query : &str) -> Result<Vec<IndexFile>, ColoredString>
+    {
+        let query = query.to_lowercase();
+        let matches = |index : &IndexFile| index.name.to_lowercase().contains(&query)
+            || index.description.as_ref().is_some_and(|d| d.to_lowercase().contains(&query));
+        match &self.kind {
+            RegistryKind::Local { dir, .. } => {
+                let entries = std::fs::read_dir(dir.join("index")).map_err(|e| e.to_string().red())?;
+                let mut found = Vec::new();
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    let Some(name) = path.file_stem().and_then(|s| s.to_str()).filter(|_| path.extension().is_some_and(|e| e == "toml")) else { continue };
+                    let index = self.index(name)?;
+                    if matches(&index) {
+                        found.push(index);
+                    }
+                }
+                found.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(found)
+            },
+            RegistryKind::Http { url } => {
+                let query_url = url::Url::parse_with_params(&format!("{url}/api/v1/search"), &[("q", &query)])
+                    .map_err(|e| e.to_string().red())?;
+                let bytes = http_get(query_url.as_str())?
+                    .ok_or(format!("{} Registry \"{}\" doesn't support search", "Registry error:".red(), self.name))?;
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| format!("{} Invalid search response from \"{}\": {e}", "Registry error:".red(), self.name).into())
+            },
+        }
+    }
+
+    /// Token from `gup login` for this registry
+    pub fn token(&self) -> Result<Option<String>, ColoredString>
+    {
+        Ok(load_credentials()?.tokens.get(&self.location).cloned())
+    }
+
+    /// Read or download an archive and check it against the checksum in its index entry
+    pub fn fetch_archive(&self, name : &str, entry : &IndexEntry) -> Result<Vec<u8>, ColoredString>
+    {
+        let bytes = match &self.kind {
+            RegistryKind::Local { dir, .. } => {
+                let path = dir.join(&entry.archive);
+                debug("Registry", &format!("Reading archive \"{}\"", path.to_string_lossy()));
+                std::fs::read(&path)
+                    .map_err(|e| format!("{} Unable to read archive for {name} {}: {e}", "Registry error:".red(), entry.version))?
+            },
+            RegistryKind::Http { url } => http_get(&format!("{url}/{}", entry.archive.trim_start_matches('/')))?
+                .ok_or(format!("{} Archive for {name} {} not found", "Registry error:".red(), entry.version))?,
+        };
+        let checksum = sha256_hex(&bytes);
+        if checksum != entry.checksum {
+            return Err(format!("{} Checksum mismatch for {name} {} (expected {}, got {checksum})",
+                "Registry error:".red(), entry.version, entry.checksum).into());
+        }
+        Ok(bytes)
+    }
+}
+
+pub fn sha256_hex(bytes : &[u8]) -> String
+{
+    Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Newest version in an index that matches a requirement and isn't yanked
+pub fn select_version<'a>(index : &'a IndexFile, requirement : &semver::VersionReq) -> Option<&'a IndexEntry>
+{
+    index.versions.iter()
+        .filter(|e| !e.yanked)
+        .filter_map(|e| semver::Version::parse(&e.version).ok().map(|v| (v, e)))
+        .filter(|(v, _)| requirement.matches(v))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, e)| e)
+}
+
+pub fn parse_requirement(requirement : &str) -> Result<semver::VersionReq, ColoredString>
+{
+    semver::VersionReq::parse(requirement)
+        .map_err(|e| format!("{} Invalid version requirement \"{requirement}\": {e}", "Registry error:".red()).into())
+}
+
+/// Unpack a `.tar.gz` package archive into a directory
+pub fn unpack_archive(bytes : &[u8], dest : &Path) -> Result<(), ColoredString>
+{
+    let decoder = flate2::read::GzDecoder::new(bytes);
+    tar::Archive::new(decoder).unpack(dest)
+        .map_err(|e| format!("{} Unable to unpack archive: {e}", "Registry error:".red()).into())
+}
+
+/// Check that an unpacked archive is the package the index says it is
+fn check_unpacked(name : &str, version : &str, dir : &Path) -> Result<(), ColoredString>
+{
+    let package = create_config_from_path(&dir.join("Config.toml"))?.package
+        .ok_or(format!("{} The archive of {name} {version} has no [package] in its Config.toml", "Registry error:".red()))?;
+    if package.name != name || package.version != version {
+        return Err(format!("{} The archive of {name} {version} contains {} {}", "Registry error:".red(), package.name, package.version).into());
+    }
+    Ok(())
+}
+
+/// Resolve a registry dependency to an exact version, reusing the lockfile if the locked version still matches.
+/// Downloads the package into the cache if it isn't there yet.
+pub fn resolve_registry_dep(name : &str, requirement : &str, registry : Option<&str>) -> Result<Dependency, ColoredString>
+{
+    let (registry_name, location) = find_registry_location(registry)?;
+    let source = registry_source(&location);
+    let requirement_str = requirement;
+    let requirement = parse_requirement(requirement)?;
+    let lock_path = PathBuf::from(crate::LOCK_PATH);
+    let lock = Lockfile::load(&lock_path)?;
+
+    let locked = lock.find(name, &source, &requirement).cloned();
+    if is_locked() && locked.is_none() {
+        return Err(format!("{} {name} {requirement_str} is not pinned in Gup.lock, but --locked was passed. Run a build without it to update Gup.lock",
+            "Lockfile error:".red()).into());
+    }
+
+    // a locked package that is vendored or cached doesn't need the registry at all.
+    // Its signature was checked when it was locked, so only the key recorded in Gup.lock is checked against the policy.
+    if let Some(locked) = &locked {
+        let dep = Dependency { name: name.into(), version: locked.version.clone(), source: source.clone() };
+        let cached = match &locked.checksum {
+            Some(checksum) => crate::cache::cached_dir_with_id(&dep, checksum)?.is_some(),
+            None => false,
+        };
+        if cached || crate::vendor::vendored_dir(&dep)?.is_some() {
+            crate::sign::check_signer(name, &locked.version, locked.signed_by.as_deref())?;
+            // the deprecation comes from the index when reading it needs no network, else from Gup.lock
+            let deprecated = match open_local_registry(&registry_name, &location)? {
+                Some(registry) => registry.find_index(name)?.and_then(|index| index.deprecated),
+                None => locked.deprecated.clone(),
+            };
+            if let Some(message) = &deprecated {
+                warn_deprecated(name, message);
+            }
+            let mut package = LockedPackage { deprecated, ..locked.clone() };
+            // fill in the file checksum of packages locked before it was recorded
+            if cached && package.integrity.is_none() {
+                package.integrity = crate::cache::CacheIndex::load()?
+                    .find_id(&dep, locked.checksum.as_deref().unwrap_or_default())
+                    .and_then(|e| e.checksum.clone());
+            }
+            if !is_locked() && &package != locked {
+                crate::lock::record(package)?;
+            }
+            return Ok(dep);
+        }
+    }
+
+    let registry = open_registry(&registry_name, &location)?;
+    let index = registry.index(name)?;
+    if let Some(message) = &index.deprecated {
+        warn_deprecated(name, message);
+    }
+    let entry = match &locked {
+        Some(locked) => index.versions.iter().find(|e| e.version == locked.version)
+            .ok_or(format!("{} Locked version {name} {} is missing from registry \"{}\"", "Registry error:".red(), locked.version, registry.name))?,
+        None => select_version(&index, &requirement)
+            .ok_or(format!("{} No version of \"{name}\" matches {requirement_str}", "Registry error:".red()))?,
+    };
+
+    if entry.yanked {
+        warn("Registry", &format!("{name} {} is yanked, but is still used because Gup.lock pins it", entry.version));
+    }
+    crate::sign::check_entry(name, entry)?;
+    if let Some(locked) = &locked && locked.signed_by.is_some() && locked.signed_by != entry.signed_by {
+        warn("Signing", &format!("{name} {} is no longer signed by the key recorded in Gup.lock", entry.version));
+    }
+
+    let dep = Dependency {
+        name: name.into(),
+        version: entry.version.clone(),
+        source: registry.source(),
+    };
+    if crate::cache::cached_dir_with_id(&dep, &entry.checksum)?.is_none() {
+        info("Registry", &format!("Downloading {name} {} from \"{}\"", entry.version, registry.name));
+        let bytes = registry.fetch_archive(name, entry)?;
+        let staged = crate::cache::StagingDir::new()?;
+        unpack_archive(&bytes, &staged.path)?;
+        check_unpacked(name, &entry.version, &staged.path)?;
+        crate::cache::add_to_cache(&dep, &entry.checksum, staged)?;
+    }
+
+    // lock new packages, and fill in the file checksum and signer of packages locked before they were recorded
+    let integrity = locked.as_ref().and_then(|p| p.integrity.clone())
+        .or(crate::cache::CacheIndex::load()?.find_id(&dep, &entry.checksum).and_then(|e| e.checksum.clone()));
+    let package = LockedPackage {
+        name: name.into(),
+        version: entry.version.clone(),
+        source: registry.source(),
+        checksum: Some(entry.checksum.clone()),
+        commit: None,
+        integrity,
+        signed_by: entry.signed_by.clone(),
+        deprecated: index.deprecated.clone(),
+    };
+    if locked.is_none() || (!is_locked() && locked.as_ref() != Some(&package)) {
+        if locked.is_none() {
+            debug("Registry", &format!("Resolved {name} {requirement_str} to {}", entry.version));
+        }
+        crate::lock::record(package)?;
+    }
+    Ok(dep)
+}
+
+/// Download a registry dependency again at its exact version, after it was removed from the cache
+pub fn refetch(dep : &Dependency) -> Result<(), ColoredString>
+{
+    let (registry, _) = registry_locations()?.into_iter()
+        .find(|(_, location)| registry_source(location) == dep.source)
+        .ok_or(format!("{} No configured registry has the source {} of {} {}", "Registry error:".red(), dep.source, dep.name, dep.version))?;
+    resolve_registry_dep(&dep.name, &format!("={}", dep.version), Some(&registry))?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use colored::{ColoredString, Colorize};
+
+use crate::config::Dependency;
+use crate::logging::*;
+use crate::cache::cached_dir;
+use crate::registry::{find_registry, open_registry, registry_locations, IndexEntry, IndexFile, Registry};
+
+/// Newest version in an index that isn't yanked, by semver
+fn latest(index : &IndexFile) -> Option<&IndexEntry>
+{
+    index.versions.iter()
+        .filter(|e| !e.yanked)
+        .filter_map(|e| semver::Version::parse(&e.version).ok().map(|v| (v, e)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, e)| e)
+}
+
+/// Whether a version of a package from a registry is already in the cache
+fn is_cached(registry : &Registry, name : &str, version : &str) -> Result<bool, ColoredString>
+{
+    let dep = Dependency { name: name.into(), version: version.into(), source: registry.source() };
+    Ok(cached_dir(&dep)?.is_some())
+}
+
+/// List packages matching a query in one registry, or in every configured registry
+pub fn search(query : &str, registry_name : Option<&str>) -> Result<(), ColoredString>
+{
+    let registries = match registry_name {
+        Some(name) => vec![find_registry(Some(name))?],
+        None => {
+            let mut registries = Vec::new();
+            for (name, location) in registry_locations()? {
+                match open_registry(&name, &location) {
+                    Ok(registry) => registries.push(registry),
+                    Err(e) => warn("Search", &format!("Skipping registry \"{name}\": {e}")),
+                }
+            }
+            registries
+        },
+    };
+    if registries.is_empty() {
+        return Err(format!("{} No registries configured in Config.toml or {}", "Search error:".red(),
+            crate::pdm::get_hc_filepath()?.join("config.toml").to_string_lossy()).into());
+    }
+
+    let mut found = 0;
+    for registry in &registries {
+        let results = match registry.search(query) {
+            Ok(results) => results,
+            Err(e) if registry_name.is_none() => {
+                warn("Search", &format!("Unable to search registry \"{}\": {e}", registry.name));
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        for index in results {
+            found += 1;
+            let version = latest(&index).map(|e| e.version.as_str()).unwrap_or("-");
+            println!("{} {} ({})", index.name.bold(), version.green(), registry.name);
+            if let Some(description) = &index.description {
+                println!("    {description}");
+            }
+            if let Some(message) = &index.deprecated {
+                println!("    {} {message}", "deprecated:".yellow());
+            }
+        }
+    }
+    if found == 0 {
+        info("Search", &format!("No packages matching \"{query}\""));
+    }
+    Ok(())
+}
+
+/// Show the description, versions and dependencies of a package, and which versions are cached
+pub fn package_info(name : &str, registry_name : Option<&str>) -> Result<(), ColoredString>
+{
+    let registry = find_registry(registry_name)?;
+    let index = registry.index(name)?;
+    let latest = latest(&index)
+        .ok_or(format!("{} \"{name}\" has no versions that aren't yanked", "Info error:".red()))?;
+
+    println!("{} {}", index.name.bold(), latest.version.green());
+    if let Some(description) = &index.description {
+        println!("{description}");
+    }
+    if let Some(message) = &index.deprecated {
+        println!("{} {message}", "Deprecated:".yellow().bold());
+    }
+    println!("{} {} ({})", "Registry:".bold(), registry.name, registry.location);
+
+    let mut versions : Vec<(semver::Version, &IndexEntry)> = index.versions.iter()
+        .filter_map(|e| semver::Version::parse(&e.version).ok().map(|v| (v, e)))
+        .collect();
+    versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+    println!("{}", "Versions:".bold());
+    for (_, entry) in &versions {
+        let yanked = if entry.yanked { " (yanked)".red() } else { "".normal() };
+        let cached = if is_cached(&registry, name, &entry.version)? { " (cached)".cyan() } else { "".normal() };
+        println!("    {}{yanked}{cached}", entry.version);
+    }
+
+    println!("{} {}", "Dependencies of".bold(), latest.version.bold());
+    if latest.dependencies.is_empty() {
+        println!("    none");
+    }
+    for (dep, value) in &latest.dependencies {
+        let requirement = match value {
+            toml::Value::String(requirement) => requirement.clone(),
+            toml::Value::Table(table) => ["version", "source", "registry"].iter()
+                .filter_map(|key| table.get(*key).and_then(|v| v.as_str()).map(|v| format!("{key} = {v}")))
+                .collect::<Vec<_>>()
+                .join(", "),
+            other => other.to_string(),
+        };
+        println!("    {dep} {requirement}");
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use colored::{ColoredString, Colorize};
+
+use crate::config::create_config_from_path;
+use crate::doc::DocFormat;
+use crate::logging::*;
+use crate::parse::{create_docs, doc_output_path};
+
+/// How often infiles are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a connection may stay idle before its request line and headers arrive
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reloads the page when the generation served at `/__reload` changes
+const RELOAD_SCRIPT: &str = r#"<script>
+const GUP_GENERATION = "{generation}";
+setInterval(() => {
+  fetch("/__reload").then(r => r.text()).then(g => { if (g !== GUP_GENERATION) location.reload(); }).catch(() => {});
+}, 1000);
+</script>
+"#;
+
+/// Generate HTML docs, serve them on localhost and regenerate them when the config or an infile changes
+pub fn serve_docs(port : u16, with_deps : bool) -> Result<(), ColoredString>
+{
+    regenerate(with_deps)?;
+    let generation = Arc::new(AtomicU64::new(0));
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("{} Unable to bind to port {port}: {e}", "Serve error:".red()))?;
+    success(&format!("Serving docs at http://127.0.0.1:{port}/ (Ctrl+C to stop)"));
+
+    let watcher_generation = Arc::clone(&generation);
+    std::thread::spawn(move || watch(with_deps, watcher_generation));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                // an idle connection (like a browser preconnect) must not hold up the others
+                let generation = Arc::clone(&generation);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, generation.load(Ordering::SeqCst)) {
+                        debug("Serve", &format!("Request failed: {e}"));
+                    }
+                });
+            },
+            Err(e) => debug("Serve", &format!("Connection failed: {e}")),
+        }
+    }
+    Ok(())
+}
+
+/// Regenerate the HTML docs through `create_docs`
+fn regenerate(with_deps : bool) -> Result<(), ColoredString>
+{
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    create_docs(cfg, DocFormat::Html, with_deps)?;
+    Ok(())
+}
+
+/// Files whose changes trigger a rebuild: the config and its infiles
+fn watched_files() -> Vec<PathBuf>
+{
+    let mut files = vec![PathBuf::from(crate::CONFIG_PATH)];
+    if let Ok(cfg) = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)) {
+        files.extend(cfg.build.infiles.iter().map(PathBuf::from));
+    }
+    files
+}
+
+fn modified_times(files : &[PathBuf]) -> Vec<Option<SystemTime>>
+{
+    files.iter()
+        .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Poll the watched files and regenerate the docs when one changes
+fn watch(with_deps : bool, generation : Arc<AtomicU64>)
+{
+    let mut files = watched_files();
+    let mut times = modified_times(&files);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let new_times = modified_times(&files);
+        if new_times == times {
+            continue;
+        }
+
+        info("Serve", "Change detected, regenerating docs");
+        match regenerate(with_deps) {
+            Ok(()) => {
+                generation.fetch_add(1, Ordering::SeqCst);
+                success("Docs regenerated");
+            },
+            Err(e) => error(&e),
+        }
+        // the config may have changed the list of infiles
+        files = watched_files();
+        times = modified_times(&files);
+    }
+}
+
+fn handle(mut stream : TcpStream, generation : u64) -> std::io::Result<()>
+{
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(&stream);
+    reader.read_line(&mut request_line)?;
+    // skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    debug("Serve", &format!("GET {path}"));
+    let (status, content_type, body) = match path {
+        "/" | "/index.html" => {
+            let docs_path = match create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)) {
+                Ok(cfg) => doc_output_path(&cfg, DocFormat::Html),
+                Err(_) => PathBuf::from(crate::DOCS_PATH).with_extension("html"),
+            };
+            match std::fs::read_to_string(&docs_path) {
+                Ok(page) => {
+                    let script = RELOAD_SCRIPT.replace("{generation}", &generation.to_string());
+                    let page = match page.rfind("</body>") {
+                        Some(end) => format!("{}{script}{}", &page[..end], &page[end..]),
+                        None => page + &script,
+                    };
+                    ("200 OK", "text/html; charset=utf-8", page)
+                },
+                Err(e) => ("500 Internal Server Error", "text/plain", format!("Unable to read docs: {e}")),
+            }
+        },
+        "/__reload" => ("200 OK", "text/plain", generation.to_string()),
+        _ => ("404 Not Found", "text/plain", String::from("Not found")),
+    };
+
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}", body.len())?;
+    stream.flush()
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use colored::{ColoredString, Colorize};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::config::{create_config_from_path, load_global_config};
+use crate::logging::*;
+use crate::pdm::get_hc_filepath;
+use crate::registry::IndexEntry;
+
+/// Secret key used by `gup publish --sign`, stored hex encoded next to the global config
+const KEY_FILE : &str = "signing.key";
+
+fn to_hex(bytes : &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex : &str) -> Option<Vec<u8>>
+{
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn key_path() -> Result<PathBuf, ColoredString>
+{
+    Ok(get_hc_filepath()?.join(KEY_FILE))
+}
+
+fn load_key() -> Result<SigningKey, ColoredString>
+{
+    let path = key_path()?;
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{} Unable to read \"{}\": {e}. Run `gup key generate` first", "Key error:".red(), path.to_string_lossy()))?;
+    let seed : [u8; 32] = from_hex(contents.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(format!("{} \"{}\" is not a signing key", "Key error:".red(), path.to_string_lossy()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Create a signing key and print its public key. Refuses to replace an existing key unless `force` is set.
+pub fn generate(force : bool) -> Result<(), ColoredString>
+{
+    let path = key_path()?;
+    if path.exists() && !force {
+        return Err(format!("{} \"{}\" already exists, pass --force to replace it", "Key error:".red(), path.to_string_lossy()).into());
+    }
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| format!("{} Unable to generate a key: {e}", "Key error:".red()))?;
+    let key = SigningKey::from_bytes(&seed);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&path)
+        .and_then(|mut file| file.write_all(to_hex(&seed).as_bytes()))
+        .map_err(|e| format!("{} Unable to write \"{}\": {e}", "Key error:".red(), path.to_string_lossy()))?;
+
+    success(&format!("Wrote a signing key to \"{}\"", path.to_string_lossy()));
+    info("Key", "Projects that depend on your packages can trust them by adding this public key to trusted_keys under [signing]");
+    println!("{}", to_hex(key.verifying_key().as_bytes()));
+    Ok(())
+}
+
+/// Print the public key of the local signing key
+pub fn show() -> Result<(), ColoredString>
+{
+    println!("{}", to_hex(load_key()?.verifying_key().as_bytes()));
+    Ok(())
+}
+
+/// Bytes that are signed for a package: its name and version as well as its checksum,
+/// so a signature can't be moved to another package or version with the same archive.
+fn signed_message(name : &str, version : &str, checksum : &str) -> String
+{
+    format!("gup-package\n{name}\n{version}\n{checksum}\n")
+}
+
+/// Sign a package with the local key. Returns the public key and the signature, hex encoded.
+pub fn sign_package(name : &str, version : &str, checksum : &str) -> Result<(String, String), ColoredString>
+{
+    let key = load_key()?;
+    let signature = key.sign(signed_message(name, version, checksum).as_bytes());
+    Ok((to_hex(key.verifying_key().as_bytes()), to_hex(&signature.to_bytes())))
+}
+
+fn signature_matches(signed_by : &str, signature : &str, message : &str) -> bool
+{
+    let key = from_hex(signed_by)
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+    let signature = from_hex(signature)
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .map(|bytes| Signature::from_bytes(&bytes));
+    match (key, signature) {
+        (Some(key), Some(signature)) => key.verify(message.as_bytes(), &signature).is_ok(),
+        _ => false,
+    }
+}
+
+/// Signature policy from `[signing]` in Config.toml and the global config
+pub struct Policy {
+    pub require: bool,
+    pub trusted_keys: Vec<String>,
+}
+
+pub fn policy() -> Result<Policy, ColoredString>
+{
+    let global = load_global_config()?.signing;
+    let project = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)).ok().and_then(|c| c.signing);
+    let require = project.as_ref().and_then(|s| s.require)
+        .or(global.as_ref().and_then(|s| s.require))
+        .unwrap_or(false);
+    let trusted_keys = project.into_iter().chain(global)
+        .flat_map(|s| s.trusted_keys)
+        .map(|key| key.to_lowercase())
+        .collect();
+    Ok(Policy { require, trusted_keys })
+}
+
+/// Check that the key a package was signed with satisfies the policy.
+/// Locked packages resolved without the registry index are checked with the key recorded in Gup.lock.
+pub fn check_signer(name : &str, version : &str, signed_by : Option<&str>) -> Result<(), ColoredString>
+{
+    let policy = policy()?;
+    if !policy.require {
+        return Ok(());
+    }
+    match signed_by {
+        None => Err(format!("{} {name} {version} is not signed, but [signing] requires signatures", "Signing error:".red()).into()),
+        Some(key) if !policy.trusted_keys.contains(&key.to_lowercase()) =>
+            Err(format!("{} {name} {version} is signed by {key}, which is not in trusted_keys", "Signing error:".red()).into()),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Check the signature of a registry package against its name, version and checksum, then against the policy.
+/// An invalid signature is rejected even if signatures aren't required.
+pub fn check_entry(name : &str, entry : &IndexEntry) -> Result<(), ColoredString>
+{
+    match (&entry.signed_by, &entry.signature) {
+        (Some(signed_by), Some(signature)) => {
+            if !signature_matches(signed_by, signature, &signed_message(name, &entry.version, &entry.checksum)) {
+                return Err(format!("{} {name} {} has an invalid signature", "Signing error:".red(), entry.version).into());
+            }
+            debug("Signing", &format!("{name} {} is signed by {signed_by}", entry.version));
+            check_signer(name, &entry.version, Some(signed_by))
+        },
+        _ => check_signer(name, &entry.version, None),
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use minijinja::{AutoEscape, Environment};
+use serde_json::Value;
+
+use crate::doc::*;
+
+/// Render docs with a user supplied template.
+///
+/// The template gets `package`, `modules` and `dependencies` (each with its own `package` and `modules`),
+/// where every module and item has an `anchor`. `format` is `"markdown"` or `"html"`, and
+/// `search_index` is the JSON search index, for templates that want their own search box.
+/// Output is HTML-escaped when rendering HTML.
+pub fn render_template(docs : &DocSet, format : DocFormat, path : &Path) -> Result<String, String>
+{
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read template {}: {e}", path.to_string_lossy()))?;
+
+    let mut env = Environment::new();
+    let html = format == DocFormat::Html;
+    env.set_auto_escape_callback(move |_| if html { AutoEscape::Html } else { AutoEscape::None });
+    env.add_filter("slug", |text : String| slug(&text));
+    env.add_template_owned(path.to_string_lossy().into_owned(), source)
+        .map_err(|e| format!("Invalid template: {e}"))?;
+
+    let mut packages = packages(docs).into_iter().map(|(set, prefix)| package_context(set, &prefix));
+    let mut context = packages.next().expect("docs should include the root package");
+    context["dependencies"] = Value::Array(packages.collect());
+    context["format"] = Value::from(format!("{format:?}").to_lowercase());
+    context["search_index"] = Value::from(serde_json::to_string(&search_index(docs)).map_err(|e| e.to_string())?);
+
+    env.get_template(&path.to_string_lossy())
+        .and_then(|t| t.render(context))
+        .map_err(|e| format!("Failed to render template: {e}"))
+}
+
+/// Template context for one package, with anchors added to its modules and items
+fn package_context(docs : &DocSet, prefix : &str) -> Value
+{
+    let modules = docs.modules.iter().map(|module| {
+        let items = module.items.iter().map(|item| {
+            let mut value = serde_json::to_value(item).unwrap_or_default();
+            value["anchor"] = Value::from(item_anchor(prefix, module, item));
+            value
+        }).collect();
+        serde_json::json!({
+            "name": module.name,
+            "file": module.file,
+            "anchor": module_anchor(prefix, module),
+            "items": Value::Array(items),
+        })
+    }).collect();
+
+    serde_json::json!({
+        "package": docs.package,
+        "anchor": format!("{prefix}package"),
+        "modules": Value::Array(modules),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(template : &str, format : DocFormat) -> String
+    {
+        let path = std::env::temp_dir().join(format!("gup-template-{}-{format:?}.j2", std::process::id()));
+        std::fs::write(&path, template).unwrap();
+        let docs = DocSet {
+            package: None,
+            modules: crate::parse::parse_source("(* @title: less\n   @signature: a < b *)", "lib.hc", &mut Vec::new()),
+            dependencies: Vec::new(),
+        };
+        let output = render_template(&docs, format, &path);
+        std::fs::remove_file(&path).unwrap();
+        output.unwrap()
+    }
+
+    const ITEMS : &str = "{{ format }}{% for module in modules %}{% for item in module.items %} {{ item.anchor }} {{ item.signature }}{% endfor %}{% endfor %}";
+
+    #[test]
+    fn items_get_anchors_and_html_is_escaped()
+    {
+        assert_eq!(render_with(ITEMS, DocFormat::Markdown), "markdown lib-less a < b");
+        assert_eq!(render_with(ITEMS, DocFormat::Html), "html lib-less a &lt; b");
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use colored::{ColoredString, Colorize};
+
+use crate::cache::{copy_tree, tree_checksum};
+use crate::config::{create_config_from_path, write_config, Config, Dependency, Vendor};
+use crate::logging::*;
+use crate::pdm::{ensure_dep_cached, entry_to_dep, get_dep_dir, get_dep_list};
+
+/// Name of the manifest in the vendor directory
+const MANIFEST : &str = "vendor.toml";
+
+/// Cleared while `gup vendor` runs, so it copies from the cache rather than from an older vendor directory
+static USE_VENDOR : AtomicBool = AtomicBool::new(true);
+
+/// Vendored directories whose checksum was already verified by this process
+static VERIFIED : Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Manifest of a vendor directory, recording where each package came from and the checksum of its files
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+#[derive(Default)]
+pub struct VendorManifest {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<VendoredPackage>,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(serde::Serialize)]
+pub struct VendoredPackage {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    /// Directory name inside the vendor directory
+    pub dir: String,
+    /// sha256 of the vendored files, see `cache::tree_checksum`
+    pub checksum: String,
+}
+
+/// Vendor directory named in the project's Config.toml
+fn vendor_dir() -> Option<PathBuf>
+{
+    if !USE_VENDOR.load(Ordering::Relaxed) {
+        return None;
+    }
+    create_config_from_path(&PathBuf::from(crate::CONFIG_PATH)).ok()
+        .and_then(|cfg| cfg.vendor)
+        .map(|vendor| PathBuf::from(vendor.directory))
+}
+
+fn load_manifest(dir : &Path) -> Result<VendorManifest, ColoredString>
+{
+    let path = dir.join(MANIFEST);
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{} Unable to read \"{}\": {e}", "Vendor error:".red(), path.to_string_lossy()))?;
+    toml::from_str(&contents).map_err(|e| (e.to_string() + &"\nCould not read vendor manifest".red()).into())
+}
+
+/// Directory of a vendored dependency, if the project is vendored and has it.
+/// Fails if the vendored files no longer match their recorded checksum.
+pub fn vendored_dir(dep : &Dependency) -> Result<Option<PathBuf>, ColoredString>
+{
+    let Some(vendor) = vendor_dir() else { return Ok(None) };
+    let manifest = load_manifest(&vendor)?;
+    let Some(package) = manifest.packages.iter().find(|p| p.name == dep.name && p.version == dep.version && p.source == dep.source) else {
+        return Ok(None);
+    };
+
+    let dir = vendor.join(&package.dir);
+    let mut verified = VERIFIED.lock().unwrap_or_else(|e| e.into_inner());
+    if !verified.contains(&dir) {
+        debug("Vendor", &format!("Verifying \"{}\"", dir.to_string_lossy()));
+        let checksum = tree_checksum(&dir)
+            .map_err(|e| format!("{} Unable to read \"{}\": {e}", "Vendor error:".red(), dir.to_string_lossy()))?;
+        if checksum != package.checksum {
+            return Err(format!("{} \"{}\" doesn't match the checksum in {MANIFEST}, it was modified after `gup vendor`",
+                "Vendor error:".red(), dir.to_string_lossy()).into());
+        }
+        verified.push(dir.clone());
+    }
+    Ok(Some(dir))
+}
+
+/// Whether a vendor directory holds an earlier `gup vendor` output that can be deleted and written again.
+/// Fails for the project directory (or one above it) and for directories with other files in them.
+fn replaceable(vendor : &Path) -> Result<bool, ColoredString>
+{
+    if !vendor.exists() {
+        return Ok(false);
+    }
+    let project = std::fs::canonicalize(".").map_err(|e| e.to_string().red())?;
+    let target = std::fs::canonicalize(vendor).map_err(|e| e.to_string().red())?;
+    if project.starts_with(&target) {
+        return Err(format!("{} \"{}\" contains the project, pick a directory inside it", "Vendor error:".red(), vendor.to_string_lossy()).into());
+    }
+    if vendor.join(MANIFEST).is_file() {
+        return Ok(true);
+    }
+    let empty = std::fs::read_dir(vendor).map_err(|e| e.to_string().red())?.next().is_none();
+    if !empty {
+        return Err(format!("{} \"{}\" is not empty and has no {MANIFEST}, so it wasn't written by `gup vendor`. Pick another directory",
+            "Vendor error:".red(), vendor.to_string_lossy()).into());
+    }
+    Ok(false)
+}
+
+/// Copy every resolved dependency into a vendor directory and point Config.toml at it
+pub fn vendor(dir : &str) -> Result<(), ColoredString>
+{
+    USE_VENDOR.store(false, Ordering::Relaxed);
+    let vendor = PathBuf::from(dir);
+    let replace = replaceable(&vendor)?;
+    let config_path = PathBuf::from(crate::CONFIG_PATH);
+    let cfg = create_config_from_path(&config_path)?;
+    for (name, value) in cfg.dependencies.iter().flatten() {
+        ensure_dep_cached(&entry_to_dep(name, value)?)?;
+    }
+    let deps = get_dep_list(&cfg)?;
+
+    if replace {
+        std::fs::remove_dir_all(&vendor).map_err(|e| e.to_string().red())?;
+    }
+    std::fs::create_dir_all(&vendor).map_err(|e| e.to_string().red())?;
+
+    let mut manifest = VendorManifest::default();
+    for dep in deps {
+        let cached = get_dep_dir(&dep)?;
+        // cache directory names are unique per source and content, so forks don't collide here either
+        let dir_name = cached.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let target = vendor.join(&dir_name);
+        debug("Vendor", &format!("Copying {} {} to \"{}\"", dep.name, dep.version, target.to_string_lossy()));
+        copy_tree(&cached, &target).map_err(|e| e.to_string().red())?;
+        let checksum = tree_checksum(&target).map_err(|e| e.to_string().red())?;
+        manifest.packages.push(VendoredPackage { name: dep.name, version: dep.version, source: dep.source, dir: dir_name, checksum });
+    }
+
+    let contents = toml::to_string(&manifest).map_err(|e| e.to_string())?;
+    std::fs::write(vendor.join(MANIFEST), format!("# This file is generated by gup vendor. Do not edit it by hand.\n{contents}"))
+        .map_err(|e| e.to_string().red())?;
+
+    let count = manifest.packages.len();
+    write_config(&Config { vendor: Some(Vendor { directory: dir.into() }), ..cfg }, crate::CONFIG_PATH.into())?;
+    success(&format!("Vendored {count} packages into \"{dir}\""));
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use colored::{ColoredString, Colorize};
+
+use crate::cache::{cache_root, remove_entries, tree_checksum, CacheEntry, CacheIndex};
+use crate::config::{create_config_from_path, Config, Dependency, OnMismatch};
+use crate::lock::Lockfile;
+use crate::logging::*;
+use crate::pdm::{ensure_dep_cached, get_dep_filename, get_dep_list};
+
+/// Checksum a cached package should have: the one pinned in Gup.lock, else the one recorded when it was fetched
+fn expected_checksum(dep : &Dependency, entry : &CacheEntry, lock : &Lockfile) -> Option<String>
+{
+    lock.packages.iter()
+        .find(|p| p.name == dep.name && p.version == dep.version && p.source == dep.source)
+        .and_then(|p| p.integrity.clone())
+        .or_else(|| entry.checksum.clone())
+}
+
+/// Check the cached files of each dependency against their checksums, and re-fetch or fail on a mismatch.
+/// Returns the number of packages checked.
+pub fn verify_deps(deps : &[Dependency], on_mismatch : OnMismatch) -> Result<usize, ColoredString>
+{
+    let lock = Lockfile::load(&PathBuf::from(crate::LOCK_PATH))?;
+    let index = CacheIndex::load()?;
+    let root = cache_root()?;
+
+    let mut checked = 0;
+    let mut mismatched = Vec::new();
+    for dep in deps {
+        // vendored copies are checked against vendor.toml when they are used
+        if crate::vendor::vendored_dir(dep)?.is_some() {
+            continue;
+        }
+        let entry = match crate::pdm::locked_id(dep)? {
+            Some(id) => index.find_id(dep, &id),
+            None => index.find(dep),
+        };
+        let Some(entry) = entry else { continue };
+        let Some(expected) = expected_checksum(dep, entry, &lock) else {
+            debug("Verify", &format!("No checksum was recorded for {} {}, skipping it", dep.name, dep.version));
+            continue;
+        };
+        let dir = root.join(&entry.dir);
+        debug("Verify", &format!("Verifying \"{}\"", dir.to_string_lossy()));
+        let actual = tree_checksum(&dir)
+            .map_err(|e| format!("{} Unable to read \"{}\": {e}", "Verify error:".red(), dir.to_string_lossy()))?;
+        checked += 1;
+        if actual != expected {
+            mismatched.push((dep, entry));
+        }
+    }
+
+    if mismatched.is_empty() {
+        return Ok(checked);
+    }
+    match on_mismatch {
+        OnMismatch::Error => {
+            for (dep, entry) in &mismatched {
+                error(&format!("{} from {} was modified after it was fetched (\"{}\")",
+                    get_dep_filename(dep)?, dep.source, root.join(&entry.dir).to_string_lossy()));
+            }
+            Err(format!("{} {} cached packages don't match their checksums. Remove them with `gup cache clean NAME`, or set on_mismatch = \"refetch\" under [verify] in Config.toml",
+                "Verify error:".red(), mismatched.len()).into())
+        },
+        OnMismatch::Refetch => {
+            for (dep, entry) in mismatched {
+                warn("Verify", &format!("{} from {} was modified after it was fetched, fetching it again", get_dep_filename(dep)?, dep.source));
+                remove_entries(|e| e.dir == entry.dir)?;
+                ensure_dep_cached(dep)?;
+            }
+            Ok(checked)
+        },
+    }
+}
+
+/// How the project wants mismatches handled, from `[verify]` in Config.toml
+pub fn on_mismatch(cfg : &Config) -> OnMismatch
+{
+    cfg.verify.as_ref().map(|v| v.on_mismatch).unwrap_or_default()
+}
+
+/// Check every dependency of the project against the checksums in Gup.lock and the cache
+pub fn verify() -> Result<(), ColoredString>
+{
+    let cfg = create_config_from_path(&PathBuf::from(crate::CONFIG_PATH))?;
+    let deps = get_dep_list(&cfg)?;
+    let checked = verify_deps(&deps, on_mismatch(&cfg))?;
+    success(&format!("Verified {checked} packages"));
+    Ok(())
+}
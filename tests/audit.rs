@@ -0,0 +1,44 @@
+//! Runs `gup audit` against an advisory database on disk
+
+mod common;
+
+use common::*;
+
+/// Write an advisory for `utils` into a database directory
+fn advisory(db : &std::path::Path, id : &str, affected : &str, patched : &str)
+{
+    std::fs::create_dir_all(db.join("utils")).unwrap();
+    std::fs::write(db.join(format!("utils/{id}.toml")),
+        format!("[advisory]\nid = \"{id}\"\npackage = \"utils\"\ntitle = \"Unchecked input\"\n\n[versions]\naffected = [\"{affected}\"]\npatched = [\"{patched}\"]\n")).unwrap();
+}
+
+#[test]
+fn audit_reports_vulnerable_dependencies()
+{
+    let env = Env::with_utils("audit");
+    let db = env.home.join("advisories");
+    advisory(&db, "GUP-0001", "<0.1.0", ">=0.1.0");
+    assert_success(&env.gup(&["audit", "--db", db.to_str().unwrap()]));
+
+    advisory(&db, "GUP-0002", "<0.1.2", ">=0.1.2");
+    let output = env.gup(&["audit", "--db", db.to_str().unwrap()]);
+    assert_failure(&output, "1 advisories affect");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("GUP-0002") && stdout.contains(">=0.1.2"), "{stdout}");
+    assert!(!stdout.contains("GUP-0001"));
+}
+
+#[test]
+fn audit_reads_database_from_config()
+{
+    let env = Env::with_utils("audit-config");
+    assert_failure(&env.gup(&["audit"]), "No advisory database");
+
+    let db = env.home.join("advisories");
+    advisory(&db, "GUP-0003", "<0.2.0", ">=0.2.0");
+    git_commit_all(&db);
+    env.append_config(&format!("\n[audit]\ndatabase = \"git+file://{}\"\n", db.to_string_lossy()));
+    let output = env.gup(&["audit"]);
+    assert_failure(&output, "1 advisories affect");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("GUP-0003"));
+}
@@ -0,0 +1,109 @@
+//! Runs gup's package cache commands, and several gup processes sharing one cache
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::*;
+
+#[test]
+fn parallel_adds_share_the_cache()
+{
+    let env = Env::new("parallel");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+    let projects : Vec<PathBuf> = (0..6).map(|i| env.new_project(&format!("project-{i}"))).collect();
+
+    std::thread::scope(|scope| {
+        let handles : Vec<_> = projects.iter().map(|project| scope.spawn(|| env.gup_in(project, &["add", "utils"]))).collect();
+        for handle in handles {
+            assert_success(&handle.join().unwrap());
+        }
+    });
+
+    let index = std::fs::read_to_string(env.home.join(".hc/packages/index.toml")).unwrap();
+    assert_eq!(index.matches("name = \"utils\"").count(), 1);
+    assert!(std::fs::read_dir(env.home.join(".hc/packages/.tmp")).map_or(true, |mut d| d.next().is_none()));
+}
+
+#[test]
+fn gup_home_and_cache_dir_override_home()
+{
+    let env = Env::new("gup-home");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+    // the registry is only configured in GUP_HOME, so HOME must not be read
+    let gup_home = env.home.join("gup-home");
+    std::fs::rename(env.home.join(".hc"), &gup_home).unwrap();
+    let run = |args : &[&str]| Command::new(env!("CARGO_BIN_EXE_gup"))
+        .args(args)
+        .current_dir(&env.project)
+        .env("HOME", &env.home)
+        .env("GUP_HOME", &gup_home)
+        .output()
+        .unwrap();
+
+    assert_success(&run(&["add", "utils"]));
+    assert!(cached_package(&gup_home, "utils-0.1.0").is_some());
+    assert!(!env.home.join(".hc").exists());
+
+    assert_success(&run(&["--cache-dir", "../shared", "update"]));
+    assert!(cached_package(&env.home.join("shared"), "utils-0.1.0").is_some());
+}
+
+#[test]
+fn xdg_layout_with_one_variable_set()
+{
+    let env = Env::new("xdg-config");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+    // only XDG_CONFIG_HOME is set, so the cache goes to the default ~/.cache
+    let config_home = env.home.join("config");
+    std::fs::create_dir_all(&config_home).unwrap();
+    std::fs::rename(env.home.join(".hc"), config_home.join("gup")).unwrap();
+    let run = |args : &[&str]| Command::new(env!("CARGO_BIN_EXE_gup"))
+        .args(args)
+        .current_dir(&env.project)
+        .env("HOME", &env.home)
+        .env("XDG_CONFIG_HOME", &config_home)
+        .env_remove("XDG_CACHE_HOME")
+        .env_remove("GUP_HOME")
+        .output()
+        .unwrap();
+
+    assert_success(&run(&["cache", "size"]));
+    assert_success(&run(&["add", "utils"]));
+    assert!(cached_package(&env.home.join(".cache/gup"), "utils-0.1.0").is_some());
+    assert!(config_home.join("gup/projects.toml").exists());
+    assert!(!env.home.join(".hc").exists());
+}
+
+#[test]
+fn cache_list_size_and_clean()
+{
+    let env = Env::with_utils("cache-list");
+    let output = env.gup(&["cache", "list"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("utils 0.1.0") && stdout.contains(&format!("registry+{}", env.registry.to_string_lossy())), "{stdout}");
+    assert_success(&env.gup(&["cache", "size"]));
+
+    assert_success(&env.gup(&["cache", "clean", "utils"]));
+    assert!(env.cached_package("utils-0.1.0").is_none());
+    assert!(!String::from_utf8_lossy(&env.gup(&["cache", "list"]).stdout).contains("utils"));
+}
+
+#[test]
+fn cache_gc_keeps_packages_of_registered_projects()
+{
+    let env = Env::with_utils("cache-gc");
+    add_to_registry(&env.registry, "tools", "0.1.0", &package_archive("tools", "0.1.0"));
+    let other = env.new_project("other");
+    assert_success(&env.gup_in(&other, &["add", "tools"]));
+    assert!(env.cached_package("tools-0.1.0").is_some());
+
+    // a project that no longer exists keeps nothing alive
+    std::fs::remove_dir_all(&other).unwrap();
+    assert_success(&env.gup(&["cache", "gc"]));
+    assert!(env.cached_package("utils-0.1.0").is_some());
+    assert!(env.cached_package("tools-0.1.0").is_none());
+    assert!(!std::fs::read_to_string(env.home.join(".hc/projects.toml")).unwrap().contains("other"));
+}
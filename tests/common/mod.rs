@@ -0,0 +1,192 @@
+//! Helpers shared by the integration tests: a scratch HOME with a default registry, and projects inside it
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use sha2::{Digest, Sha256};
+
+/// A `.tar.gz` package with a Config.toml and one source file
+pub fn package_archive(name : &str, version : &str) -> Vec<u8>
+{
+    let config = format!("[package]\nname = \"{name}\"\nversion = \"{version}\"\n\n[build]\ninfiles = [\"./lib.hc\"]\noutfile = \"./a.wasm\"\n");
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (path, contents) in [("Config.toml", config.as_str()), ("lib.hc", "module Lib = {}\n")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, contents.as_bytes()).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+pub fn sha256_hex(bytes : &[u8]) -> String
+{
+    Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Add a version of a package to a directory registry, laid out the way `gup publish` writes it
+pub fn add_to_registry(registry : &Path, name : &str, version : &str, archive : &[u8])
+{
+    let archive_path = format!("archives/{name}-{version}.tar.gz");
+    std::fs::create_dir_all(registry.join("archives")).unwrap();
+    std::fs::write(registry.join(&archive_path), archive).unwrap();
+
+    let index_path = registry.join("index").join(format!("{name}.toml"));
+    let mut index : toml::Table = std::fs::read_to_string(&index_path).ok()
+        .map(|contents| contents.parse().unwrap())
+        .unwrap_or_else(|| toml::Table::from_iter([("name".to_string(), name.into())]));
+    let mut entry = toml::Table::new();
+    entry.insert("version".into(), version.into());
+    entry.insert("checksum".into(), sha256_hex(archive).into());
+    entry.insert("archive".into(), archive_path.into());
+    index.entry("version").or_insert(toml::Value::Array(Vec::new()))
+        .as_array_mut().unwrap()
+        .push(entry.into());
+    std::fs::write(index_path, toml::to_string(&index).unwrap()).unwrap();
+}
+
+/// Commit every file in a directory to a new git repository, returning the commit id
+pub fn git_commit_all(dir : &Path) -> String
+{
+    let repo = git2::Repository::open(dir).or_else(|_| git2::Repository::init(dir)).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("gup tests", "tests@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    repo.commit(Some("HEAD"), &signature, &signature, "Update", &tree, &parent.iter().collect::<Vec<_>>())
+        .unwrap()
+        .to_string()
+}
+
+/// A package in its own git repository, for git dependencies
+pub fn git_package(dir : &Path, name : &str, version : &str, source : &str) -> String
+{
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("Config.toml"),
+        format!("[package]\nname = \"{name}\"\nversion = \"{version}\"\n\n[build]\ninfiles = [\"./lib.hc\"]\noutfile = \"./a.wasm\"\n")).unwrap();
+    std::fs::write(dir.join("lib.hc"), source).unwrap();
+    git_commit_all(dir)
+}
+
+/// A scratch HOME, its default registry, and a project named `demo` inside it
+pub struct Env {
+    pub home: PathBuf,
+    pub project: PathBuf,
+    /// Directory of the default registry, when it is a local directory
+    pub registry: PathBuf,
+}
+
+impl Env {
+    /// A fresh HOME whose default registry is an empty directory registry
+    pub fn new(test : &str) -> Env
+    {
+        let env = Env::with_registry_location(test, "");
+        std::fs::create_dir_all(env.registry.join("index")).unwrap();
+        env.set_registry(&env.registry.to_string_lossy());
+        env
+    }
+
+    /// A fresh HOME whose default registry is at `location`
+    pub fn with_registry_location(test : &str, location : &str) -> Env
+    {
+        let home = std::env::temp_dir().join(format!("gup-test-{test}-{}", std::process::id()));
+        if home.exists() {
+            std::fs::remove_dir_all(&home).unwrap();
+        }
+        std::fs::create_dir_all(home.join(".hc")).unwrap();
+        let env = Env { registry: home.join("registry"), project: home.join("demo"), home };
+        env.set_registry(location);
+        env.new_project("demo");
+        env
+    }
+
+    /// A registry holding utils 0.1.0, and a project that depends on it
+    pub fn with_utils(test : &str) -> Env
+    {
+        let env = Env::new(test);
+        add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+        assert_success(&env.gup(&["add", "utils"]));
+        env
+    }
+
+    /// Point the global config's default registry somewhere else
+    pub fn set_registry(&self, location : &str)
+    {
+        std::fs::write(self.home.join(".hc/config.toml"), format!("[registries]\ndefault = \"{location}\"\n")).unwrap();
+    }
+
+    /// Another project in the same HOME
+    pub fn new_project(&self, name : &str) -> PathBuf
+    {
+        let project = self.home.join(name);
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::write(project.join("Config.toml"),
+            format!("[package]\nname = \"{name}\"\nversion = \"0.2.0\"\n\n[build]\ninfiles = [\"./main.hc\"]\noutfile = \"./a.wasm\"\n")).unwrap();
+        std::fs::write(project.join("main.hc"), "module Main = {}\n").unwrap();
+        project
+    }
+
+    pub fn gup(&self, args : &[&str]) -> Output
+    {
+        self.gup_in(&self.project, args)
+    }
+
+    pub fn gup_in(&self, project : &Path, args : &[&str]) -> Output
+    {
+        Command::new(env!("CARGO_BIN_EXE_gup"))
+            .args(args)
+            .current_dir(project)
+            .env("HOME", &self.home)
+            .env("NO_COLOR", "1")
+            .env_remove("GUP_HOME")
+            .env_remove("XDG_CACHE_HOME")
+            .env_remove("XDG_CONFIG_HOME")
+            .output()
+            .unwrap()
+    }
+
+    /// Append to the project's Config.toml
+    pub fn append_config(&self, text : &str)
+    {
+        let mut config = std::fs::read_to_string(self.project.join("Config.toml")).unwrap();
+        config.push_str(text);
+        std::fs::write(self.project.join("Config.toml"), config).unwrap();
+    }
+
+    pub fn lockfile(&self) -> String
+    {
+        std::fs::read_to_string(self.project.join("Gup.lock")).unwrap_or_default()
+    }
+
+    /// Directory of a package in the cache, by its `name-version` prefix
+    pub fn cached_package(&self, prefix : &str) -> Option<PathBuf>
+    {
+        cached_package(&self.home.join(".hc"), prefix)
+    }
+}
+
+/// Directory of a package in a cache directory, by its `name-version` prefix
+pub fn cached_package(cache : &Path, prefix : &str) -> Option<PathBuf>
+{
+    std::fs::read_dir(cache.join("packages")).ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&format!("{prefix}-"))))
+}
+
+pub fn assert_success(output : &Output)
+{
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// Assert that gup failed with an error containing `message`
+pub fn assert_failure(output : &Output, message : &str)
+{
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "expected a failure mentioning \"{message}\"");
+    assert!(stderr.contains(message), "expected \"{message}\" in:\n{stderr}");
+}
@@ -0,0 +1,48 @@
+//! Runs gup with git dependencies cloned from repositories on disk
+
+mod common;
+
+use common::*;
+
+#[test]
+fn git_dependency_commit_is_pinned()
+{
+    let env = Env::new("git-pin");
+    let repo = env.home.join("tools");
+    let first = git_package(&repo, "tools", "0.1.0", "module Tools = { one }\n");
+    let url = format!("file://{}", repo.to_string_lossy());
+    assert_success(&env.gup(&["add", "--url", &url]));
+    assert!(env.lockfile().contains(&format!("commit = \"{first}\"")), "{}", env.lockfile());
+
+    // a newer commit upstream doesn't move the pin, even when the package is cloned again
+    let second = git_package(&repo, "tools", "0.1.0", "module Tools = { two }\n");
+    assert_success(&env.gup(&["cache", "clean"]));
+    assert_success(&env.gup(&["tree", "--locked"]));
+    let cached = env.cached_package("tools-0.1.0").unwrap();
+    assert!(std::fs::read_to_string(cached.join("lib.hc")).unwrap().contains("one"));
+
+    assert_success(&env.gup(&["update"]));
+    let lock = env.lockfile();
+    assert!(lock.contains(&format!("commit = \"{second}\"")) && !lock.contains(&first), "{lock}");
+
+    std::fs::remove_file(env.project.join("Gup.lock")).unwrap();
+    assert_failure(&env.gup(&["tree", "--locked"]), "tools from file://");
+    assert_success(&env.gup(&["tree"]));
+    assert!(env.lockfile().contains(&format!("commit = \"{second}\"")));
+}
+
+#[test]
+fn fetch_clones_the_pinned_commit()
+{
+    let env = Env::new("git-fetch");
+    let repo = env.home.join("tools");
+    git_package(&repo, "tools", "0.1.0", "module Tools = { one }\n");
+    assert_success(&env.gup(&["add", "--url", &format!("file://{}", repo.to_string_lossy())]));
+    git_package(&repo, "tools", "0.1.0", "module Tools = { two }\n");
+    assert_success(&env.gup(&["cache", "clean"]));
+
+    assert_success(&env.gup(&["fetch"]));
+    let cached = env.cached_package("tools-0.1.0").unwrap();
+    assert!(std::fs::read_to_string(cached.join("lib.hc")).unwrap().contains("one"));
+    assert_success(&env.gup(&["tree", "--frozen"]));
+}
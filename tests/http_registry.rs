@@ -0,0 +1,311 @@
+//! Runs gup against a stand-in HTTP registry served from a local socket
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use common::*;
+
+/// A request received by the stand-in registry
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    /// `X-Gup-Signed-By` and `X-Gup-Signature` headers sent by `gup publish --sign`
+    signed_by: Option<String>,
+    signature: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Stand-in registry serving fixed responses by path, and recording every request
+struct Server {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    fn start(routes : Vec<(&str, u16, Vec<u8>)>) -> Server
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let routes : Vec<(String, u16, Vec<u8>)> = routes.into_iter().map(|(p, s, b)| (p.into(), s, b)).collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut authorization = None;
+                let mut signed_by = None;
+                let mut signature = None;
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (key, value) = header.split_once(':').unwrap();
+                    match key.to_ascii_lowercase().as_str() {
+                        "authorization" => authorization = Some(value.trim().to_string()),
+                        "x-gup-signed-by" => signed_by = Some(value.trim().to_string()),
+                        "x-gup-signature" => signature = Some(value.trim().to_string()),
+                        "content-length" => length = value.trim().parse().unwrap(),
+                        _ => {},
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, content) = routes.iter()
+                    .find(|(p, _, _)| *p == path)
+                    .map(|(_, s, b)| (*s, b.clone()))
+                    .unwrap_or((404, Vec::new()));
+                write!(stream, "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", content.len()).unwrap();
+                stream.write_all(&content).unwrap();
+                recorded.lock().unwrap().push(Request { method, path, authorization, signed_by, signature, body });
+            }
+        });
+        Server { url, requests }
+    }
+}
+
+fn index_json(name : &str, version : &str, checksum : &str) -> Vec<u8>
+{
+    serde_json::json!({
+        "name": name,
+        "version": [{ "version": version, "checksum": checksum, "archive": format!("archives/{name}-{version}.tar.gz") }],
+    }).to_string().into_bytes()
+}
+
+/// A fresh HOME whose default registry is the server
+fn setup(test : &str, server : &Server) -> Env
+{
+    Env::with_registry_location(&format!("http-{test}"), &format!("sparse+{}", server.url))
+}
+
+#[test]
+fn add_downloads_from_sparse_index()
+{
+    let archive = package_archive("utils", "0.1.0");
+    let server = Server::start(vec![
+        ("/index/utils.json", 200, index_json("utils", "0.1.0", &sha256_hex(&archive))),
+        ("/archives/utils-0.1.0.tar.gz", 200, archive),
+    ]);
+    let env = setup("add", &server);
+
+    let output = env.gup(&["add", "utils"]);
+    assert_success(&output);
+    assert!(std::fs::read_to_string(env.project.join("Config.toml")).unwrap().contains("utils = \"^0.1.0\""));
+    assert!(env.lockfile().contains(&format!("registry+sparse+{}", server.url)));
+    assert!(env.cached_package("utils-0.1.0").is_some_and(|dir| dir.join("lib.hc").exists()));
+}
+
+#[test]
+fn add_rejects_checksum_mismatch()
+{
+    let archive = package_archive("utils", "0.1.0");
+    let server = Server::start(vec![
+        ("/index/utils.json", 200, index_json("utils", "0.1.0", &sha256_hex(b"something else"))),
+        ("/archives/utils-0.1.0.tar.gz", 200, archive),
+    ]);
+    let env = setup("checksum", &server);
+
+    let output = env.gup(&["add", "utils"]);
+    assert_failure(&output, "Checksum mismatch");
+    assert!(env.cached_package("utils-0.1.0").is_none());
+}
+
+#[test]
+fn add_reports_missing_package()
+{
+    let server = Server::start(Vec::new());
+    let env = setup("missing", &server);
+
+    let output = env.gup(&["add", "nothing"]);
+    assert_failure(&output, "not found in registry");
+}
+
+#[test]
+fn publish_sends_token_from_login()
+{
+    let server = Server::start(vec![("/api/v1/packages/demo/0.2.0", 200, Vec::new())]);
+    let env = setup("publish", &server);
+
+    let output = env.gup(&["login", "--token", "secret"]);
+    assert_success(&output);
+    let output = env.gup(&["publish", "--no-verify"]);
+    assert_success(&output);
+
+    let requests = server.requests.lock().unwrap();
+    let upload = requests.iter().find(|r| r.method == "PUT").expect("publish should upload the archive");
+    assert_eq!(upload.path, "/api/v1/packages/demo/0.2.0");
+    assert_eq!(upload.authorization.as_deref(), Some("Bearer secret"));
+    assert!(!upload.body.is_empty());
+}
+
+#[test]
+fn publish_without_login_fails()
+{
+    let server = Server::start(Vec::new());
+    let env = setup("nologin", &server);
+
+    let output = env.gup(&["publish", "--no-verify"]);
+    assert_failure(&output, "gup login");
+    assert!(!server.requests.lock().unwrap().iter().any(|r| r.method == "PUT"));
+}
+
+#[test]
+fn publish_reports_rejected_token()
+{
+    let server = Server::start(vec![("/api/v1/packages/demo/0.2.0", 401, Vec::new())]);
+    let env = setup("rejected", &server);
+
+    env.gup(&["login", "--token", "wrong"]);
+    let output = env.gup(&["publish", "--no-verify"]);
+    assert_failure(&output, "rejected the token");
+}
+
+#[test]
+fn search_lists_matches()
+{
+    let mut index : serde_json::Value = serde_json::from_slice(&index_json("utils", "0.1.0", "0")).unwrap();
+    index["description"] = serde_json::Value::from("Handy helpers");
+    let server = Server::start(vec![("/api/v1/search?q=help", 200, serde_json::json!([index]).to_string().into_bytes())]);
+    let env = setup("search", &server);
+
+    let output = env.gup(&["search", "help"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("utils 0.1.0 (default)"));
+    assert!(stdout.contains("Handy helpers"));
+}
+
+#[test]
+fn add_skips_yanked_and_warns_deprecated()
+{
+    let archive = package_archive("utils", "0.1.0");
+    let index = serde_json::json!({
+        "name": "utils",
+        "deprecated": "use tools instead",
+        "version": [
+            { "version": "0.1.0", "checksum": sha256_hex(&archive), "archive": "archives/utils-0.1.0.tar.gz" },
+            { "version": "0.2.0", "checksum": "0", "archive": "archives/utils-0.2.0.tar.gz", "yanked": true },
+        ],
+    });
+    let server = Server::start(vec![
+        ("/index/utils.json", 200, index.to_string().into_bytes()),
+        ("/archives/utils-0.1.0.tar.gz", 200, archive),
+    ]);
+    let env = setup("yanked", &server);
+
+    let output = env.gup(&["add", "utils"]);
+    assert_success(&output);
+    assert!(std::fs::read_to_string(env.project.join("Config.toml")).unwrap().contains("utils = \"^0.1.0\""));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("utils is deprecated: use tools instead"));
+
+    // the locked and cached package is used without asking the registry, so builds warn from Gup.lock
+    assert!(env.lockfile().contains("deprecated = \"use tools instead\""));
+    let sent = server.requests.lock().unwrap().len();
+    for args in [&["build"][..], &["build", "--offline"]] {
+        assert!(String::from_utf8_lossy(&env.gup(args).stderr).contains("utils is deprecated: use tools instead"));
+    }
+    assert_eq!(server.requests.lock().unwrap().len(), sent);
+
+    assert!(String::from_utf8_lossy(&env.gup(&["update"]).stderr).contains("utils is deprecated"));
+}
+
+#[test]
+fn yank_sends_delete()
+{
+    let server = Server::start(vec![("/api/v1/packages/utils/0.2.0/yank", 200, Vec::new())]);
+    let env = setup("yank", &server);
+
+    env.gup(&["login", "--token", "secret"]);
+    let output = env.gup(&["yank", "utils@0.2.0"]);
+    assert_success(&output);
+
+    let requests = server.requests.lock().unwrap();
+    let yank = requests.iter().find(|r| r.path == "/api/v1/packages/utils/0.2.0/yank").expect("yank should reach the registry");
+    assert_eq!(yank.method, "DELETE");
+    assert_eq!(yank.authorization.as_deref(), Some("Bearer secret"));
+}
+
+#[test]
+fn signed_packages_required_by_policy()
+{
+    let publisher = Server::start(vec![("/api/v1/packages/demo/0.2.0", 200, Vec::new())]);
+    let env = setup("signing", &publisher);
+    let output = env.gup(&["key", "generate"]);
+    assert_success(&output);
+    let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    assert!(env.gup(&["login", "--token", "secret"]).status.success());
+    let output = env.gup(&["publish", "--no-verify", "--sign"]);
+    assert_success(&output);
+
+    let (archive, signature) = {
+        let requests = publisher.requests.lock().unwrap();
+        let upload = requests.iter().find(|r| r.method == "PUT").unwrap();
+        assert_eq!(upload.signed_by.as_deref(), Some(key.as_str()));
+        (upload.body.clone(), upload.signature.clone().expect("publish --sign should send a signature"))
+    };
+    let signed_index = |name : &str, signature : &str| serde_json::json!({
+        "name": name,
+        "version": [{ "version": "0.2.0", "checksum": sha256_hex(&archive), "archive": "archives/demo-0.2.0.tar.gz",
+            "signed_by": key, "signature": signature }],
+    }).to_string().into_bytes();
+    let mut forged = signature.clone();
+    forged.replace_range(..2, if signature.starts_with("00") { "11" } else { "00" });
+    let unsigned = package_archive("utils", "0.1.0");
+    let consumer = Server::start(vec![
+        ("/index/demo.json", 200, signed_index("demo", &signature)),
+        ("/index/forged.json", 200, signed_index("forged", &forged)),
+        ("/archives/demo-0.2.0.tar.gz", 200, archive.clone()),
+        ("/index/utils.json", 200, index_json("utils", "0.1.0", &sha256_hex(&unsigned))),
+        ("/archives/utils-0.1.0.tar.gz", 200, unsigned),
+    ]);
+    env.set_registry(&format!("sparse+{}", consumer.url));
+
+    // an invalid signature is rejected even without a policy
+    let output = env.gup(&["add", "forged"]);
+    assert_failure(&output, "invalid signature");
+
+    env.append_config("\n[signing]\nrequire = true\n");
+    let output = env.gup(&["add", "demo"]);
+    assert_failure(&output, "not in trusted_keys");
+
+    env.append_config(&format!("trusted_keys = [\"{key}\"]\n"));
+    let output = env.gup(&["add", "demo"]);
+    assert_success(&output);
+    assert!(env.lockfile().contains(&format!("signed_by = \"{key}\"")));
+
+    let output = env.gup(&["add", "utils"]);
+    assert_failure(&output, "is not signed");
+}
+
+#[test]
+fn offline_uses_cache_and_sends_no_requests()
+{
+    let archive = package_archive("utils", "0.1.0");
+    let server = Server::start(vec![
+        ("/index/utils.json", 200, index_json("utils", "0.1.0", &sha256_hex(&archive))),
+        ("/archives/utils-0.1.0.tar.gz", 200, archive),
+    ]);
+    let env = setup("offline", &server);
+    assert_success(&env.gup(&["add", "utils"]));
+    let sent = server.requests.lock().unwrap().len();
+
+    assert_success(&env.gup(&["tree", "--offline"]));
+    assert_failure(&env.gup(&["add", "tools", "--offline"]), "--offline was passed");
+    assert_eq!(server.requests.lock().unwrap().len(), sent);
+}
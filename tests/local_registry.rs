@@ -0,0 +1,182 @@
+//! Runs gup against directory and git registries on disk
+
+mod common;
+
+use common::*;
+
+#[test]
+fn add_from_directory_registry()
+{
+    let env = Env::with_utils("local-add");
+    assert!(std::fs::read_to_string(env.project.join("Config.toml")).unwrap().contains("utils = \"^0.1.0\""));
+    assert!(env.lockfile().contains(&format!("source = \"registry+{}\"", env.registry.to_string_lossy())));
+    assert!(env.cached_package("utils-0.1.0").is_some_and(|dir| dir.join("lib.hc").exists()));
+
+    let output = env.gup(&["tree"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("utils-0.1.0"));
+}
+
+#[test]
+fn publish_to_directory_registry()
+{
+    let env = Env::new("local-publish");
+    let output = env.gup(&["publish", "--no-verify"]);
+    assert_success(&output);
+    assert!(env.registry.join("archives/demo-0.2.0.tar.gz").exists());
+    assert!(std::fs::read_to_string(env.registry.join("index/demo.toml")).unwrap().contains("version = \"0.2.0\""));
+    assert_failure(&env.gup(&["publish", "--no-verify"]), "already published");
+
+    let consumer = env.new_project("consumer");
+    assert_success(&env.gup_in(&consumer, &["add", "demo"]));
+    assert!(std::fs::read_to_string(consumer.join("Gup.lock")).unwrap().contains("name = \"demo\""));
+}
+
+#[test]
+fn publish_verification_leaves_nothing_behind()
+{
+    let env = Env::new("local-verify");
+    // whether or not hcc is installed, the packed copy is removed and never registered as a project
+    env.gup(&["publish", "--dry-run"]);
+    assert!(std::fs::read_dir(env.home.join(".hc/packages/.tmp")).map_or(true, |mut d| d.next().is_none()));
+    assert!(!std::fs::read_to_string(env.home.join(".hc/projects.toml")).unwrap_or_default().contains(".tmp"));
+}
+
+#[test]
+fn yank_hides_version_from_new_resolutions()
+{
+    let env = Env::new("local-yank");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+    add_to_registry(&env.registry, "utils", "0.1.1", &package_archive("utils", "0.1.1"));
+    assert_success(&env.gup(&["yank", "utils@0.1.1"]));
+    assert!(std::fs::read_to_string(env.registry.join("index/utils.toml")).unwrap().contains("yanked = true"));
+
+    assert_success(&env.gup(&["add", "utils"]));
+    assert!(env.lockfile().contains("version = \"0.1.0\""));
+}
+
+#[test]
+fn build_warns_about_deprecation_after_locking()
+{
+    let env = Env::with_utils("local-deprecate");
+    assert_success(&env.gup(&["deprecate", "utils", "use tools instead"]));
+    let stderr = String::from_utf8_lossy(&env.gup(&["build"]).stderr).to_string();
+    assert_eq!(stderr.matches("utils is deprecated: use tools instead").count(), 1, "{stderr}");
+    assert!(env.lockfile().contains("deprecated = \"use tools instead\""));
+}
+
+#[test]
+fn add_from_git_registry()
+{
+    let env = Env::new("git-registry");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("utils", "0.1.0"));
+    git_commit_all(&env.registry);
+    env.set_registry(&format!("git+file://{}", env.registry.to_string_lossy()));
+
+    assert_success(&env.gup(&["add", "utils"]));
+    assert!(env.cached_package("utils-0.1.0").is_some());
+    assert!(std::fs::read_dir(env.home.join(".hc/registries")).unwrap().next().is_some());
+}
+
+#[test]
+fn frozen_uses_only_lockfile_and_cache()
+{
+    let env = Env::with_utils("frozen");
+    // without the registry, only Gup.lock and the cache are left
+    std::fs::rename(&env.registry, env.home.join("registry-gone")).unwrap();
+    let output = env.gup(&["tree", "--frozen"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("utils-0.1.0"));
+
+    std::fs::remove_file(env.project.join("Gup.lock")).unwrap();
+    assert_failure(&env.gup(&["tree", "--locked"]), "not pinned in Gup.lock");
+}
+
+#[test]
+fn fetch_downloads_without_building()
+{
+    let env = Env::with_utils("fetch");
+    assert_success(&env.gup(&["cache", "clean"]));
+
+    let output = env.gup(&["fetch", "--jobs", "2"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Fetched 1 packages"));
+    assert!(env.cached_package("utils-0.1.0").is_some());
+
+    std::fs::rename(&env.registry, env.home.join("registry-gone")).unwrap();
+    assert_success(&env.gup(&["tree", "--frozen"]));
+}
+
+#[test]
+fn lock_keeps_one_entry_per_required_version()
+{
+    let env = Env::new("multi-version");
+    for version in ["1.0.0", "1.1.0", "2.0.0"] {
+        add_to_registry(&env.registry, "foo", version, &package_archive("foo", version));
+    }
+    env.append_config("\n[dependencies]\nfoo-one = { name = \"foo\", version = \"~1.0.0\" }\nfoo = \"^2.0.0\"\n");
+    assert_success(&env.gup(&["tree"]));
+    let lock = env.lockfile();
+    assert!(lock.contains("version = \"1.0.0\"") && lock.contains("version = \"2.0.0\""), "{lock}");
+
+    // both pins are found again, so nothing is re-resolved
+    assert_success(&env.gup(&["tree", "--locked"]));
+    assert_eq!(env.lockfile(), lock);
+
+    let config = std::fs::read_to_string(env.project.join("Config.toml")).unwrap().replace("~1.0.0", "^1.0.0");
+    std::fs::write(env.project.join("Config.toml"), config).unwrap();
+    assert_success(&env.gup(&["update"]));
+    let lock = env.lockfile();
+    assert!(lock.contains("version = \"1.1.0\"") && lock.contains("version = \"2.0.0\""), "{lock}");
+    assert!(!lock.contains("version = \"1.0.0\""), "{lock}");
+}
+
+#[test]
+fn add_rejects_archive_of_another_package()
+{
+    let env = Env::new("wrong-archive");
+    add_to_registry(&env.registry, "utils", "0.1.0", &package_archive("tools", "0.1.0"));
+    add_to_registry(&env.registry, "utils", "0.1.1", &package_archive("utils", "0.2.0"));
+    assert_failure(&env.gup(&["add", "utils@=0.1.0"]), "The archive of utils 0.1.0 contains tools 0.1.0");
+    assert_failure(&env.gup(&["add", "utils@=0.1.1"]), "The archive of utils 0.1.1 contains utils 0.2.0");
+    assert!(env.cached_package("utils").is_none() && env.cached_package("tools").is_none());
+}
+
+#[test]
+fn signature_covers_name_and_version()
+{
+    let env = Env::new("signed-local");
+    assert_success(&env.gup(&["key", "generate"]));
+    assert_success(&env.gup(&["publish", "--no-verify", "--sign"]));
+    let consumer = env.new_project("consumer");
+    assert_success(&env.gup_in(&consumer, &["add", "demo"]));
+
+    // the same archive and signature presented as another version
+    let index_path = env.registry.join("index/demo.toml");
+    let index = std::fs::read_to_string(&index_path).unwrap();
+    std::fs::write(&index_path, index.replace("version = \"0.2.0\"", "version = \"0.2.1\"")).unwrap();
+    let other = env.new_project("other");
+    assert_failure(&env.gup_in(&other, &["add", "demo"]), "demo 0.2.1 has an invalid signature");
+}
+
+#[test]
+fn forks_with_the_same_version_are_cached_side_by_side()
+{
+    let env = Env::with_utils("forks");
+    let fork = env.home.join("fork");
+    std::fs::create_dir_all(fork.join("index")).unwrap();
+    let mut archive = package_archive("utils", "0.1.0");
+    archive.extend_from_slice(&[0; 16]);
+    add_to_registry(&fork, "utils", "0.1.0", &archive);
+
+    let consumer = env.new_project("consumer");
+    std::fs::write(consumer.join("Config.toml"), format!("{}\n[registries]\nfork = \"{}\"\n",
+        std::fs::read_to_string(consumer.join("Config.toml")).unwrap(), fork.to_string_lossy())).unwrap();
+    assert_success(&env.gup_in(&consumer, &["add", "utils", "--registry", "fork"]));
+
+    let cached = std::fs::read_dir(env.home.join(".hc/packages")).unwrap()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("utils-0.1.0-"))
+        .count();
+    assert_eq!(cached, 2);
+}
@@ -0,0 +1,43 @@
+//! Runs `gup vendor` and builds from the vendored copies
+
+mod common;
+
+use common::*;
+
+#[test]
+fn vendored_dependencies_resolve_without_registry_or_cache()
+{
+    let env = Env::with_utils("vendor");
+    assert_success(&env.gup(&["vendor"]));
+    assert!(std::fs::read_to_string(env.project.join("Config.toml")).unwrap().contains("[vendor]"));
+    assert!(env.project.join("vendor/vendor.toml").exists());
+
+    assert_success(&env.gup(&["cache", "clean"]));
+    std::fs::rename(&env.registry, env.home.join("registry-gone")).unwrap();
+    assert_success(&env.gup(&["tree", "--frozen"]));
+
+    let dir = std::fs::read_dir(env.project.join("vendor")).unwrap()
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| p.is_dir())
+        .unwrap();
+    std::fs::write(dir.join("lib.hc"), "module Lib = { tampered }\n").unwrap();
+    assert_failure(&env.gup(&["tree", "--frozen"]), "doesn't match the checksum");
+}
+
+#[test]
+fn vendor_only_replaces_its_own_directory()
+{
+    let env = Env::with_utils("vendor-target");
+    assert_failure(&env.gup(&["vendor", "."]), "contains the project");
+    assert!(env.project.join("Config.toml").exists() && env.project.join("main.hc").exists());
+
+    std::fs::create_dir_all(env.project.join("src")).unwrap();
+    std::fs::write(env.project.join("src/lib.hc"), "module Lib = {}\n").unwrap();
+    assert_failure(&env.gup(&["vendor", "src"]), "has no vendor.toml");
+    assert!(env.project.join("src/lib.hc").exists());
+
+    // a directory written by `gup vendor` is replaced
+    assert_success(&env.gup(&["vendor", "deps"]));
+    assert_success(&env.gup(&["vendor", "deps"]));
+}
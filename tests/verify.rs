@@ -0,0 +1,48 @@
+//! Runs `gup verify` against modified packages in the cache
+
+mod common;
+
+use common::*;
+
+#[test]
+fn verify_detects_modified_cache()
+{
+    let env = Env::with_utils("verify");
+    assert!(env.lockfile().contains("integrity = "));
+    assert_success(&env.gup(&["verify"]));
+
+    let cached = env.cached_package("utils-0.1.0").unwrap();
+    std::fs::write(cached.join("lib.hc"), "module Lib = { tampered }\n").unwrap();
+    assert_failure(&env.gup(&["verify"]), "was modified after it was fetched");
+
+    env.append_config("\n[verify]\non_mismatch = \"refetch\"\n");
+    assert_success(&env.gup(&["verify"]));
+    let cached = env.cached_package("utils-0.1.0").unwrap();
+    assert!(!std::fs::read_to_string(cached.join("lib.hc")).unwrap().contains("tampered"));
+}
+
+#[test]
+fn verify_checks_git_and_path_dependencies()
+{
+    let env = Env::new("verify-sources");
+    let repo = env.home.join("tools");
+    git_package(&repo, "tools", "0.1.0", "module Tools = {}\n");
+    let local = env.home.join("shapes");
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::write(local.join("Config.toml"),
+        "[package]\nname = \"shapes\"\nversion = \"0.3.0\"\n\n[build]\ninfiles = [\"./lib.hc\"]\noutfile = \"./a.wasm\"\n").unwrap();
+    std::fs::write(local.join("lib.hc"), "module Shapes = {}\n").unwrap();
+    assert_success(&env.gup(&["add", "--url", &format!("file://{}", repo.to_string_lossy())]));
+    assert_success(&env.gup(&["add", "--path", &local.to_string_lossy()]));
+    assert_eq!(env.lockfile().matches("integrity = ").count(), 2, "{}", env.lockfile());
+    assert_success(&env.gup(&["verify"]));
+
+    for package in ["tools-0.1.0", "shapes-0.3.0"] {
+        let cached = env.cached_package(package).unwrap();
+        std::fs::write(cached.join("lib.hc"), "tampered\n").unwrap();
+        assert_failure(&env.gup(&["verify"]), "was modified after it was fetched");
+        std::fs::remove_dir_all(&cached).unwrap();
+        assert_success(&env.gup(&["fetch"]));
+    }
+    assert_success(&env.gup(&["verify"]));
+}